@@ -0,0 +1,170 @@
+use crate::errors::SparusError;
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::{
+  sync::{Arc, RwLock},
+  time::{Duration, Instant},
+};
+use tauri::{command, AppHandle, Runtime, State};
+use tauri_plugin_store::StoreExt;
+
+/// Store key holding the `BandwidthConfig`.
+pub const BANDWIDTH_KEY: &str = "bandwidth";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BandwidthConfig {
+  /// Download limit in bytes per second. `None` means unlimited.
+  #[serde(default)]
+  pub limit: Option<u64>,
+  /// Limits that replace `limit` during part of the day, in local time.
+  #[serde(default)]
+  pub schedule: Vec<ScheduledLimit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledLimit {
+  /// `HH:MM`, inclusive.
+  pub start: String,
+  /// `HH:MM`, exclusive. An `end` before `start` spans midnight.
+  pub end: String,
+  pub limit: Option<u64>,
+}
+
+impl ScheduledLimit {
+  fn range(&self) -> Result<(NaiveTime, NaiveTime), SparusError> {
+    let parse = |time: &str| {
+      NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|err| SparusError::Bandwidth(format!("invalid schedule time {time}: {err}")))
+    };
+    Ok((parse(&self.start)?, parse(&self.end)?))
+  }
+
+  fn contains(&self, now: NaiveTime) -> bool {
+    match self.range() {
+      Ok((start, end)) if start <= end => start <= now && now < end,
+      Ok((start, end)) => now >= start || now < end,
+      Err(_) => false,
+    }
+  }
+}
+
+impl BandwidthConfig {
+  /// Checks what the store can't: that schedule times parse, that no range is
+  /// empty, and that no limit is 0, which would stop downloads.
+  pub fn validate(&self) -> Result<(), SparusError> {
+    let check_limit = |limit: Option<u64>| match limit {
+      Some(0) => Err(SparusError::Bandwidth(
+        "a limit of 0 would stop downloads, use none for unlimited".to_string(),
+      )),
+      _ => Ok(()),
+    };
+    check_limit(self.limit)?;
+    for scheduled in &self.schedule {
+      let (start, end) = scheduled.range()?;
+      if start == end {
+        return Err(SparusError::Bandwidth(format!(
+          "the schedule starting at {} is empty",
+          scheduled.start
+        )));
+      }
+      check_limit(scheduled.limit)?;
+    }
+    Ok(())
+  }
+
+  fn current_limit(&self) -> Option<u64> {
+    let now = Local::now().time();
+    match self
+      .schedule
+      .iter()
+      .find(|scheduled| scheduled.contains(now))
+    {
+      Some(scheduled) => scheduled.limit,
+      None => self.limit,
+    }
+  }
+}
+
+/// Download limit shared by game updates and plugin downloads.
+///
+/// Downloads read the limit on every chunk, so changing it applies to the
+/// downloads that are already running.
+#[derive(Clone, Default)]
+pub struct BandwidthLimiter {
+  config: Arc<RwLock<BandwidthConfig>>,
+}
+
+impl BandwidthLimiter {
+  pub fn set(&self, config: BandwidthConfig) {
+    if let Ok(mut current) = self.config.write() {
+      *current = config;
+    }
+  }
+
+  fn config(&self) -> BandwidthConfig {
+    self
+      .config
+      .read()
+      .map(|config| config.clone())
+      .unwrap_or_default()
+  }
+
+  pub fn throttle(&self) -> Throttle {
+    Throttle {
+      limiter: self.clone(),
+      last_bytes: 0,
+      last_instant: Instant::now(),
+    }
+  }
+}
+
+/// Paces one download against the `BandwidthLimiter` it was created from.
+pub struct Throttle {
+  limiter: BandwidthLimiter,
+  last_bytes: u64,
+  last_instant: Instant,
+}
+
+impl Throttle {
+  /// How long to wait, after `total_bytes` were downloaded, to stay under the
+  /// current limit.
+  pub fn delay(&mut self, total_bytes: u64) -> Option<Duration> {
+    let downloaded = total_bytes.saturating_sub(self.last_bytes);
+    self.last_bytes = total_bytes;
+
+    let Some(limit) = self
+      .limiter
+      .config()
+      .current_limit()
+      .filter(|limit| *limit > 0)
+    else {
+      self.last_instant = Instant::now();
+      return None;
+    };
+
+    let expected = Duration::from_secs_f64(downloaded as f64 / limit as f64);
+    let delay = expected.saturating_sub(self.last_instant.elapsed());
+    self.last_instant = Instant::now() + delay;
+    (!delay.is_zero()).then_some(delay)
+  }
+}
+
+#[command]
+pub fn get_bandwidth_limit(limiter: State<'_, BandwidthLimiter>) -> BandwidthConfig {
+  limiter.config()
+}
+
+#[command]
+pub fn set_bandwidth_limit<R: Runtime>(
+  app: AppHandle<R>,
+  limiter: State<'_, BandwidthLimiter>,
+  config: BandwidthConfig,
+) -> Result<(), SparusError> {
+  config.validate()?;
+
+  let store = app.store("Sparus.json")?;
+  store.set(BANDWIDTH_KEY, serde_json::to_value(&config)?);
+  store.save()?;
+  limiter.set(config);
+  Ok(())
+}
@@ -1,4 +1,7 @@
-use libspeedupdate::{link::RepositoryError, workspace::UpdateError};
+use libspeedupdate::{
+  link::RepositoryError,
+  workspace::{CheckError, UpdateError},
+};
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::{io, path};
 use tauri_plugin_http::reqwest;
@@ -13,6 +16,12 @@ pub enum SparusError {
   Repository(#[from] RepositoryError),
   #[error("{0}")]
   Game(String),
+  #[error("Game {0} not found")]
+  GameNotFound(String),
+  #[error("No installation folder was chosen")]
+  NoWorkspaceRoot,
+  #[error("{0} is outside the workspace root")]
+  OutsideWorkspaceRoot(String),
   #[error(transparent)]
   Json(#[from] serde_json::Error),
   #[error(transparent)]
@@ -33,12 +42,44 @@ pub enum SparusError {
   Wasmtime(#[from] wasmtime::Error),
   #[error("Plugin event {0} not found")]
   PluginEvent(String),
-  #[error("Plugin not found")]
-  Plugin,
   #[error("{0}")]
   PluginInternal(String),
+  #[error("{0}")]
+  PluginDownload(String),
+  #[error("{url}: the server answered {status}")]
+  PluginDownloadStatus { url: String, status: u16 },
+  #[error("{0}")]
+  PluginSignature(String),
+  #[error("{0}")]
+  LauncherSignature(String),
+  #[error("{0}")]
+  PluginLimit(String),
+  #[error("{0}")]
+  PluginValue(String),
+  #[error("Invalid plugin name {0}")]
+  InvalidPluginName(String),
+  #[error("The permissions of plugin {0} were not approved")]
+  PermissionsDeclined(String),
+  #[error("Plugin {0} is disabled")]
+  PluginDisabled(String),
+  #[error("Plugin {plugin} is quarantined after failing repeatedly: {last_error}")]
+  PluginQuarantined { plugin: String, last_error: String },
+  #[error("Plugin {plugin} prevented the launch: {reason}")]
+  LaunchVetoed { plugin: String, reason: String },
   #[error("No version was provided")]
   NoVersion,
+  #[error("Update {0} was cancelled")]
+  UpdateCancelled(String),
+  #[error("Update {0} not found")]
+  UpdateNotFound(String),
+  #[error("{0}")]
+  Bandwidth(String),
+  #[error("Not enough disk space on {path}: {required} bytes required, {available} available")]
+  InsufficientSpace {
+    path: String,
+    required: u64,
+    available: u64,
+  },
 }
 
 impl From<UpdateError> for SparusError {
@@ -47,6 +88,12 @@ impl From<UpdateError> for SparusError {
   }
 }
 
+impl From<CheckError> for SparusError {
+  fn from(error: CheckError) -> Self {
+    SparusError::Update(error.to_string())
+  }
+}
+
 impl Serialize for SparusError {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
@@ -70,6 +117,21 @@ impl Serialize for SparusError {
         s.serialize_field("kind", "game")?;
         s.serialize_field("message", &err.to_string())?;
       }
+      SparusError::GameNotFound(game_id) => {
+        s.serialize_field("kind", "game")?;
+        s.serialize_field("message", &format!("Game {} not found", game_id))?;
+      }
+      SparusError::NoWorkspaceRoot => {
+        s.serialize_field("kind", "no_workspace_root")?;
+        s.serialize_field("message", &self.to_string())?;
+      }
+      SparusError::OutsideWorkspaceRoot(path) => {
+        s.serialize_field("kind", "game")?;
+        s.serialize_field(
+          "message",
+          &format!("{} is outside the workspace root", path),
+        )?;
+      }
       SparusError::Json(err) => {
         s.serialize_field("kind", "json")?;
         s.serialize_field("message", &err.to_string())?;
@@ -110,18 +172,86 @@ impl Serialize for SparusError {
         s.serialize_field("kind", "plugin")?;
         s.serialize_field("message", &format!("Plugin event {} not found", event_type))?;
       }
-      SparusError::Plugin => {
-        s.serialize_field("kind", "plugin")?;
-        s.serialize_field("message", "Plugin not found")?;
-      }
       SparusError::PluginInternal(err) => {
         s.serialize_field("kind", "plugin")?;
         s.serialize_field("message", &err.to_string())?;
       }
+      SparusError::PluginDownload(err) => {
+        s.serialize_field("kind", "plugin_download")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::PluginDownloadStatus { url, status } => {
+        s.serialize_field("kind", "plugin_download")?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("url", url)?;
+        s.serialize_field("status", status)?;
+      }
+      SparusError::PluginSignature(err) => {
+        s.serialize_field("kind", "plugin_signature")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::LauncherSignature(err) => {
+        s.serialize_field("kind", "launcher_signature")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::PluginLimit(err) => {
+        s.serialize_field("kind", "plugin_limit")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::PluginValue(err) => {
+        s.serialize_field("kind", "plugin_value")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::InvalidPluginName(_) => {
+        s.serialize_field("kind", "invalid_plugin_name")?;
+        s.serialize_field("message", &self.to_string())?;
+      }
+      SparusError::PermissionsDeclined(_) => {
+        s.serialize_field("kind", "permissions_declined")?;
+        s.serialize_field("message", &self.to_string())?;
+      }
+      SparusError::PluginDisabled(plugin) => {
+        s.serialize_field("kind", "plugin_disabled")?;
+        s.serialize_field("message", &format!("Plugin {} is disabled", plugin))?;
+      }
+      SparusError::PluginQuarantined { plugin, .. } => {
+        s.serialize_field("kind", "quarantined")?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("plugin", plugin)?;
+      }
+      SparusError::LaunchVetoed { plugin, reason } => {
+        s.serialize_field("kind", "launch_vetoed")?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("plugin", plugin)?;
+        s.serialize_field("reason", reason)?;
+      }
       SparusError::NoVersion => {
         s.serialize_field("kind", "version")?;
         s.serialize_field("message", "No version was provided")?;
       }
+      SparusError::UpdateCancelled(update_id) => {
+        s.serialize_field("kind", "update_cancelled")?;
+        s.serialize_field("message", &format!("Update {} was cancelled", update_id))?;
+      }
+      SparusError::UpdateNotFound(update_id) => {
+        s.serialize_field("kind", "update")?;
+        s.serialize_field("message", &format!("Update {} not found", update_id))?;
+      }
+      SparusError::Bandwidth(err) => {
+        s.serialize_field("kind", "bandwidth")?;
+        s.serialize_field("message", &err.to_string())?;
+      }
+      SparusError::InsufficientSpace {
+        path,
+        required,
+        available,
+      } => {
+        s.serialize_field("kind", "insufficient_space")?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("path", path)?;
+        s.serialize_field("required", required)?;
+        s.serialize_field("available", available)?;
+      }
     }
     s.end()
   }
@@ -0,0 +1,76 @@
+use crate::errors::SparusError;
+use serde::{Deserialize, Serialize};
+use std::{
+  fs::{self, OpenOptions},
+  io::{BufRead, BufReader, ErrorKind, Write},
+};
+use tauri::{command, AppHandle, Manager, Runtime};
+
+/// Log of every update run, one JSON `UpdateRecord` per line, in the app data
+/// directory.
+const HISTORY_FILE: &str = "update_history.jsonl";
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateRecord {
+  pub update_id: String,
+  pub game_id: Option<String>,
+  pub workspace_path: String,
+  pub from_version: Option<String>,
+  /// The installed version after a successful update, or else the version
+  /// that was requested, if any.
+  pub to_version: Option<String>,
+  /// RFC 3339, UTC.
+  pub started_at: String,
+  pub ended_at: String,
+  pub downloaded_bytes: u64,
+  pub failed_files: usize,
+  /// The `SparusError` the update failed with, serialized as the frontend
+  /// receives it.
+  pub error: Option<serde_json::Value>,
+}
+
+pub fn record<R: Runtime>(app: &AppHandle<R>, record: &UpdateRecord) -> Result<(), SparusError> {
+  let app_data_dir = app.path().app_data_dir()?;
+  fs::create_dir_all(&app_data_dir)?;
+  let mut line = serde_json::to_vec(record)?;
+  line.push(b'\n');
+  // One `write_all` on an append-only file, so records of updates finishing
+  // together don't interleave.
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(app_data_dir.join(HISTORY_FILE))?
+    .write_all(&line)?;
+  Ok(())
+}
+
+/// Recorded updates, most recent first, optionally only those of `game_id` and
+/// at most `limit` of them.
+///
+/// Lines that can't be parsed are skipped rather than failing the whole
+/// history, which has to stay readable when something went wrong.
+#[command]
+pub fn get_update_history<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: Option<String>,
+  limit: Option<usize>,
+) -> Result<Vec<UpdateRecord>, SparusError> {
+  let path = app.path().app_data_dir()?.join(HISTORY_FILE);
+  let file = match fs::File::open(path) {
+    Ok(file) => file,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(err.into()),
+  };
+
+  let mut records: Vec<UpdateRecord> = BufReader::new(file)
+    .lines()
+    .map_while(Result::ok)
+    .filter_map(|line| serde_json::from_str(&line).ok())
+    .filter(|record: &UpdateRecord| game_id.is_none() || record.game_id == game_id)
+    .collect();
+  records.reverse();
+  if let Some(limit) = limit {
+    records.truncate(limit);
+  }
+  Ok(records)
+}
@@ -0,0 +1,170 @@
+use crate::{
+  errors::SparusError,
+  plugins::{self, read_manifest, PluginSystem},
+  quarantine, rpc,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::fs;
+
+/// Interface plugins export their hooks in.
+pub const HOOKS_INTERFACE: &str = "sparus:launcher/hooks";
+
+/// A point of the launcher's flow plugins can be called at, named after the
+/// function of `sparus:launcher/hooks` that is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hook {
+  #[serde(rename = "on-before-update")]
+  BeforeUpdate,
+  #[serde(rename = "on-after-update")]
+  AfterUpdate,
+  #[serde(rename = "on-before-launch")]
+  BeforeLaunch,
+  #[serde(rename = "on-game-exit")]
+  GameExit,
+}
+
+impl Hook {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Hook::BeforeUpdate => "on-before-update",
+      Hook::AfterUpdate => "on-after-update",
+      Hook::BeforeLaunch => "on-before-launch",
+      Hook::GameExit => "on-game-exit",
+    }
+  }
+}
+
+/// The `update-info` record the update hooks are called with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateInfo {
+  pub game_id: Option<String>,
+  pub workspace_path: String,
+  pub goal_version: Option<String>,
+}
+
+/// Installed plugins whose manifest declares `hook`, sorted by name so that
+/// they are always called in the same order. Plugins that are disabled,
+/// quarantined or need a newer launcher are left out.
+async fn hooked_plugins<R: Runtime>(
+  app: &AppHandle<R>,
+  hook: Hook,
+) -> Result<Vec<String>, SparusError> {
+  let launcher_version = &app.package_info().version;
+  let plugins_dir = app.path().app_data_dir()?.join("plugins");
+  let mut skipped = plugins::disabled_plugins(app)?;
+  skipped.extend(quarantine::quarantined_plugins(app)?);
+
+  if !plugins_dir.exists() {
+    return Ok(Vec::new());
+  }
+
+  let mut entries = fs::read_dir(&plugins_dir).await?;
+  let mut plugins = Vec::new();
+  while let Some(entry) = entries.next_entry().await? {
+    let plugin = entry.file_name().to_string_lossy().to_string();
+    if skipped.contains(&plugin) {
+      continue;
+    }
+    // A broken manifest only keeps its own plugin out.
+    if let Ok(Some(manifest)) = read_manifest(&entry.path()).await {
+      if manifest.hooks.contains(&hook) && manifest.is_compatible(launcher_version) {
+        plugins.push(plugin);
+      }
+    }
+  }
+  plugins.sort();
+  Ok(plugins)
+}
+
+/// Calls `hook` of each plugin that declares it, one after the other, until
+/// `stop` returns an error for the result of one.
+///
+/// A plugin that fails to run its hook is reported through
+/// `sparus://pluginerror` and doesn't hold the launcher up: a broken plugin
+/// must not be able to prevent updates or launches.
+async fn run<R: Runtime>(
+  app: &AppHandle<R>,
+  hook: Hook,
+  args: Vec<Value>,
+  stop: impl Fn(&str, Value) -> Result<(), SparusError>,
+) -> Result<(), SparusError> {
+  let plugins = match hooked_plugins(app, hook).await {
+    Ok(plugins) => plugins,
+    Err(err) => {
+      rpc::report(app, err);
+      return Ok(());
+    }
+  };
+
+  let plugin_system = app.state::<PluginSystem>();
+  for plugin in plugins {
+    match plugin_system
+      .call(
+        app,
+        plugin.clone(),
+        Some(HOOKS_INTERFACE),
+        hook.as_str().to_string(),
+        args.clone(),
+      )
+      .await
+    {
+      Ok(result) => stop(&plugin, result)?,
+      Err(err) => rpc::report(app, err),
+    }
+  }
+  Ok(())
+}
+
+pub async fn before_update<R: Runtime>(app: &AppHandle<R>, update: &UpdateInfo) {
+  let _ = run(app, Hook::BeforeUpdate, vec![json!(update)], |_, _| Ok(())).await;
+}
+
+pub async fn after_update<R: Runtime>(
+  app: &AppHandle<R>,
+  update: &UpdateInfo,
+  error: Option<&SparusError>,
+) {
+  let error = error.map(|err| err.to_string());
+  let _ = run(
+    app,
+    Hook::AfterUpdate,
+    vec![json!(update), json!(error)],
+    |_, _| Ok(()),
+  )
+  .await;
+}
+
+/// Fails with `SparusError::LaunchVetoed` for the first plugin whose
+/// `on-before-launch` returns an error.
+pub async fn before_launch<R: Runtime>(
+  app: &AppHandle<R>,
+  game_id: Option<&str>,
+  executable: &str,
+) -> Result<(), SparusError> {
+  run(
+    app,
+    Hook::BeforeLaunch,
+    vec![json!(game_id), json!(executable)],
+    |plugin, result| match result.get("err") {
+      Some(reason) => Err(SparusError::LaunchVetoed {
+        plugin: plugin.to_string(),
+        reason: reason.as_str().unwrap_or_default().to_string(),
+      }),
+      None => Ok(()),
+    },
+  )
+  .await
+}
+
+pub async fn game_exit<R: Runtime>(app: &AppHandle<R>, game_id: Option<&str>, code: Option<i32>) {
+  let _ = run(
+    app,
+    Hook::GameExit,
+    vec![json!(game_id), json!(code)],
+    |_, _| Ok(()),
+  )
+  .await;
+}
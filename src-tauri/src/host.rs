@@ -0,0 +1,132 @@
+use crate::{
+  library::{self, UpdateTarget},
+  plugins::{ComponentRunStates, HostAccess},
+  updater::{self, LocalSpawner},
+  utils::{self, RunningGames},
+};
+use std::path::PathBuf;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tauri_plugin_notification::NotificationExt;
+
+wasmtime::component::bindgen!({
+  path: "wit",
+  world: "plugin",
+});
+
+pub use sparus::launcher::host::add_to_linker;
+
+/// The launcher state and actions the `sparus:launcher/host` imports are
+/// backed by.
+///
+/// It hides the `Runtime` of the `AppHandle` behind a trait object, so that
+/// the `Store` of a plugin doesn't have to be generic over it.
+pub trait Launcher: Send + Sync {
+  fn installed_version(&self, game_id: &str) -> Result<Option<String>, String>;
+  fn workspace_path(&self, game_id: &str) -> Result<PathBuf, String>;
+  fn game_running(&self, game_id: &str) -> bool;
+  fn start_update(&self, game_id: &str) -> Result<(), String>;
+  fn notify(&self, title: &str, body: &str);
+  fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String>;
+}
+
+impl<R: Runtime> Launcher for AppHandle<R> {
+  fn installed_version(&self, game_id: &str) -> Result<Option<String>, String> {
+    let game = library::game(self, game_id).map_err(|err| err.to_string())?;
+    let workspace_path = PathBuf::from(&game.workspace_path);
+    if !workspace_path.join(".update").join("state.json").is_file() {
+      return Ok(None);
+    }
+    utils::version(self.clone(), &workspace_path)
+      .map(Some)
+      .map_err(|err| err.to_string())
+  }
+
+  fn workspace_path(&self, game_id: &str) -> Result<PathBuf, String> {
+    library::game(self, game_id)
+      .map(|game| PathBuf::from(game.workspace_path))
+      .map_err(|err| err.to_string())
+  }
+
+  fn game_running(&self, game_id: &str) -> bool {
+    self
+      .workspace_path(game_id)
+      .ok()
+      .and_then(|workspace_path| workspace_path.canonicalize().ok())
+      .is_some_and(|workspace_path| self.state::<RunningGames>().any_running_in(&workspace_path))
+  }
+
+  fn start_update(&self, game_id: &str) -> Result<(), String> {
+    let target = UpdateTarget::resolve(self, Some(game_id), None, None, None)
+      .map_err(|err| err.to_string())?;
+    let window = self
+      .get_webview_window("main")
+      .ok_or("The launcher window is not open")?
+      .as_ref()
+      .window();
+    let app = self.clone();
+    let update_id = game_id.to_string();
+    tauri::async_runtime::spawn(async move {
+      let goal_version = target.pinned_version.clone();
+      // The update reports its progress and errors to the frontend itself.
+      let _ = updater::run_update(
+        window,
+        &app.state::<LocalSpawner<R>>(),
+        &target,
+        goal_version,
+        update_id,
+      )
+      .await;
+    });
+    Ok(())
+  }
+
+  fn notify(&self, title: &str, body: &str) {
+    let _ = self.notification().builder().title(title).body(body).show();
+  }
+
+  fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String> {
+    Emitter::emit(self, event, payload).map_err(|err| err.to_string())
+  }
+}
+
+impl sparus::launcher::host::Host for ComponentRunStates {
+  fn installed_version(&mut self, game_id: String) -> Result<Option<String>, String> {
+    self.check_access(HostAccess::Games)?;
+    self.launcher.installed_version(&game_id)
+  }
+
+  fn workspace_path(&mut self, game_id: String) -> Result<String, String> {
+    self.check_access(HostAccess::Games)?;
+    self
+      .launcher
+      .workspace_path(&game_id)
+      .map(|workspace_path| workspace_path.display().to_string())
+  }
+
+  fn game_running(&mut self, game_id: String) -> bool {
+    self.launcher.game_running(&game_id)
+  }
+
+  fn start_update(&mut self, game_id: String) -> Result<(), String> {
+    self.check_access(HostAccess::Updates)?;
+    self.launcher.start_update(&game_id)
+  }
+
+  fn notify(&mut self, title: String, body: String) -> Result<(), String> {
+    self.check_access(HostAccess::Notifications)?;
+    self.launcher.notify(&title, &body);
+    Ok(())
+  }
+
+  /// Events are namespaced by plugin, so a plugin can't pass for the launcher
+  /// or for another plugin.
+  fn emit(&mut self, event: String, payload: String) -> Result<(), String> {
+    self.check_access(HostAccess::Events)?;
+    let payload =
+      serde_json::from_str(&payload).map_err(|err| format!("invalid payload: {err}"))?;
+    self.launcher.emit(
+      &format!("sparus://plugin/{}/{event}", self.plugin_name),
+      payload,
+    )
+  }
+}
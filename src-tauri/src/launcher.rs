@@ -0,0 +1,319 @@
+use crate::{
+  errors::SparusError,
+  library::{Channel, UpdateTarget},
+  rpc,
+  updater::{self, LocalSpawner},
+  utils,
+};
+use minisign_verify::PublicKey;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{env, ffi::OsString, fs, io, path::PathBuf};
+#[cfg(desktop)]
+use std::{
+  process::Command,
+  thread,
+  time::{Duration, Instant},
+};
+use tauri::{command, AppHandle, Manager, Runtime, Window};
+use tauri_plugin_store::StoreExt;
+
+/// Store key holding the repository the launcher binary is updated from.
+const LAUNCHER_REPOSITORY_KEY: &str = "launcher_repository_url";
+/// Minisign public keys, base64 encoded and separated by whitespace or commas,
+/// one of which must sign every launcher update.
+///
+/// They are built in rather than read from the store: the webview can write
+/// the store, and with it `launcher_repository_url`. A launcher built without
+/// keys doesn't update itself.
+const LAUNCHER_PUBLIC_KEYS: Option<&str> = option_env!("SPARUS_LAUNCHER_PUBLIC_KEYS");
+/// `update_id` of the launcher update, to pause or cancel it like a game update.
+pub const LAUNCHER_UPDATE_ID: &str = "launcher";
+/// How long a new launcher binary has to start before it is rolled back.
+#[cfg(desktop)]
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+  /// Downloaded to the staging workspace, swapped in on the next start.
+  Staged,
+  /// Swapped in, waiting for the new binary to confirm it started.
+  Trial,
+  /// The new binary started. The backup is removed on the next start.
+  Confirmed,
+  /// The new binary failed to start and the previous one was restored.
+  RolledBack,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingUpdate {
+  version: String,
+  status: Status,
+}
+
+/// Files of a launcher self-update, next to the launcher executable since
+/// that is the directory that must be writable to replace it anyway.
+///
+/// The staging directory is a regular speedupdate workspace and is kept
+/// between updates, so the next update only downloads what changed.
+struct Paths {
+  exe: PathBuf,
+  backup: PathBuf,
+  staging: PathBuf,
+  staged_exe: PathBuf,
+  marker: PathBuf,
+}
+
+impl Paths {
+  fn new() -> io::Result<Self> {
+    // `current_exe` is inside the read-only mount for an AppImage.
+    #[cfg(target_os = "linux")]
+    let exe = match env::var_os("APPIMAGE") {
+      Some(appimage) => PathBuf::from(appimage),
+      None => env::current_exe()?,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let exe = env::current_exe()?;
+
+    let (Some(dir), Some(file_name)) = (exe.parent(), exe.file_name()) else {
+      return Err(io::Error::other("Unable to locate the launcher executable"));
+    };
+    let update_dir = dir.join(".sparus-update");
+    let staging = update_dir.join("staging");
+    let mut backup_name = OsString::from(file_name);
+    backup_name.push(".old");
+
+    Ok(Self {
+      backup: exe.with_file_name(backup_name),
+      staged_exe: staging.join(file_name),
+      staging,
+      marker: update_dir.join("pending.json"),
+      exe,
+    })
+  }
+
+  fn read(&self) -> Option<PendingUpdate> {
+    let content = fs::read(&self.marker).ok()?;
+    serde_json::from_slice(&content).ok()
+  }
+
+  fn write(&self, pending: &PendingUpdate) -> Result<(), SparusError> {
+    fs::write(&self.marker, serde_json::to_vec(pending)?)?;
+    Ok(())
+  }
+}
+
+/// Swaps in a staged launcher update. Must run before the app is built, so
+/// that the single instance plugin of this process doesn't block the new
+/// binary.
+///
+/// The new binary is started as a child and this process waits for it to call
+/// `confirm_update`, then exits. If the child exits or times out first, the
+/// previous binary is restored and this process goes on starting normally.
+#[cfg(desktop)]
+pub fn finish_pending_update() {
+  let Ok(paths) = Paths::new() else {
+    return;
+  };
+  let Some(mut pending) = paths.read() else {
+    return;
+  };
+
+  match pending.status {
+    Status::Staged => {
+      let trial = swap(&paths).and_then(|_| {
+        pending.status = Status::Trial;
+        paths.write(&pending).map_err(io::Error::other)?;
+        run_trial(&paths)
+      });
+      if let Ok(true) = trial {
+        std::process::exit(0);
+      }
+      if restore(&paths).is_ok() {
+        pending.status = Status::RolledBack;
+        let _ = paths.write(&pending);
+      }
+    }
+    Status::Confirmed => {
+      let _ = fs::remove_file(&paths.backup);
+      let _ = fs::remove_file(&paths.marker);
+    }
+    // `Trial` is the new binary started by `run_trial`.
+    Status::Trial | Status::RolledBack => {}
+  }
+}
+
+/// Marks a launcher update in trial as started. Called once the main window
+/// is built.
+#[cfg(desktop)]
+pub fn confirm_update() {
+  let Ok(paths) = Paths::new() else {
+    return;
+  };
+  if let Some(mut pending) = paths.read() {
+    if let Status::Trial = pending.status {
+      pending.status = Status::Confirmed;
+      let _ = paths.write(&pending);
+    }
+  }
+}
+
+#[cfg(desktop)]
+fn swap(paths: &Paths) -> io::Result<()> {
+  if !paths.staged_exe.is_file() {
+    return Err(io::Error::from(io::ErrorKind::NotFound));
+  }
+  // A running executable can be renamed, even on Windows, but not overwritten.
+  fs::rename(&paths.exe, &paths.backup)?;
+  fs::copy(&paths.staged_exe, &paths.exe)?;
+  Ok(())
+}
+
+#[cfg(desktop)]
+fn restore(paths: &Paths) -> io::Result<()> {
+  if paths.backup.exists() {
+    let _ = fs::remove_file(&paths.exe);
+    fs::rename(&paths.backup, &paths.exe)?;
+  }
+  Ok(())
+}
+
+#[cfg(desktop)]
+fn run_trial(paths: &Paths) -> io::Result<bool> {
+  let mut child = Command::new(&paths.exe)
+    .args(env::args_os().skip(1))
+    .spawn()?;
+  let started = Instant::now();
+  while started.elapsed() < TRIAL_TIMEOUT {
+    if let Some(PendingUpdate {
+      status: Status::Confirmed,
+      ..
+    }) = paths.read()
+    {
+      return Ok(true);
+    }
+    if child.try_wait()?.is_some() {
+      return Ok(false);
+    }
+    thread::sleep(Duration::from_millis(250));
+  }
+  let _ = child.kill();
+  let _ = child.wait();
+  Ok(false)
+}
+
+fn public_keys() -> Result<Vec<PublicKey>, SparusError> {
+  LAUNCHER_PUBLIC_KEYS
+    .unwrap_or_default()
+    .split(|c: char| c == ',' || c.is_whitespace())
+    .filter(|key| !key.is_empty())
+    .map(|key| {
+      PublicKey::from_base64(key).map_err(|err| {
+        SparusError::LauncherSignature(format!("Invalid launcher public key {key}: {err}"))
+      })
+    })
+    .collect()
+}
+
+/// Checks the staged executable against its `.minisig`, shipped next to it in
+/// the launcher repository, before it is ever run.
+fn verify_staged_exe(paths: &Paths, public_keys: &[PublicKey]) -> Result<(), SparusError> {
+  let file_name = paths
+    .staged_exe
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let signature_file = paths.staging.join(format!("{file_name}.minisig"));
+  let signature = fs::read_to_string(&signature_file)
+    .map_err(|err| SparusError::LauncherSignature(format!("{file_name} is not signed: {err}")))?;
+  let content = fs::read(&paths.staged_exe)?;
+  rpc::check_signature(&file_name, &content, &signature, public_keys)
+    .map_err(SparusError::LauncherSignature)
+}
+
+fn repository_url<R: Runtime>(app: &AppHandle<R>) -> Result<String, SparusError> {
+  let store = app.store("Sparus.json")?;
+  match store.get(LAUNCHER_REPOSITORY_KEY) {
+    Some(tauri_plugin_store::JsonValue::String(url)) if !url.is_empty() => Ok(url),
+    _ => Err(SparusError::Update(
+      "No launcher repository url was configured".to_string(),
+    )),
+  }
+}
+
+/// Whether the launcher repository has a newer version than the running
+/// launcher. A version that is already staged, or that was rolled back, is not
+/// offered again, and nothing is offered to a launcher built without public
+/// keys.
+#[command]
+pub async fn launcher_update_available<R: Runtime>(
+  handle: AppHandle<R>,
+) -> Result<bool, SparusError> {
+  if public_keys()?.is_empty() {
+    return Ok(false);
+  }
+  let remote_version = updater::latest_remote_version(repository_url(&handle)?, None)
+    .await?
+    .version()
+    .to_string();
+  if let Some(pending) = Paths::new()?.read() {
+    if pending.version == remote_version && !matches!(pending.status, Status::Confirmed) {
+      return Ok(false);
+    }
+  }
+  Ok(Version::parse(&remote_version)? > handle.package_info().version)
+}
+
+/// Downloads the latest launcher to the staging workspace, emitting
+/// `sparus://downloadinfos` like a game update, and returns its version.
+///
+/// Only the launcher executable is replaced, the frontend still comes from
+/// hotswap. The swap happens on the next start, so the frontend restarts the
+/// launcher with the process plugin once this returns.
+#[command]
+pub async fn apply_launcher_update<R: Runtime>(
+  window: Window<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+) -> Result<String, SparusError> {
+  let handle = window.app_handle().clone();
+  let public_keys = public_keys()?;
+  if public_keys.is_empty() {
+    return Err(SparusError::LauncherSignature(
+      "This launcher was built without public keys to verify its updates".to_string(),
+    ));
+  }
+  let paths = Paths::new()?;
+  fs::create_dir_all(&paths.staging)?;
+
+  let target = UpdateTarget {
+    game_id: None,
+    repository_url: repository_url(&handle)?,
+    workspace_path: paths.staging.clone(),
+    auth: None,
+    channel: Channel::Stable,
+    pinned_version: None,
+  };
+  updater::run_update(
+    window,
+    &spawner,
+    &target,
+    None,
+    LAUNCHER_UPDATE_ID.to_string(),
+  )
+  .await?;
+
+  if !paths.staged_exe.is_file() {
+    return Err(SparusError::Update(format!(
+      "The launcher repository has no {}",
+      paths.staged_exe.display()
+    )));
+  }
+  verify_staged_exe(&paths, &public_keys)?;
+  let version = utils::version(handle, &paths.staging)?;
+  paths.write(&PendingUpdate {
+    version: version.clone(),
+    status: Status::Staged,
+  })?;
+  Ok(version)
+}
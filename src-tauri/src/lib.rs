@@ -9,9 +9,18 @@ use tauri_plugin_autostart::MacosLauncher;
 #[cfg(mobile)]
 use tauri_plugin_fs::FsExt;
 
+mod bandwidth;
 mod errors;
+mod history;
+mod hooks;
+mod host;
+mod launcher;
+mod library;
 mod plugins;
+mod quarantine;
 mod rpc;
+mod scheduler;
+mod settings;
 #[cfg(desktop)]
 mod tray;
 mod updater;
@@ -23,6 +32,9 @@ pub fn run() {
 }
 
 pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
+  #[cfg(desktop)]
+  launcher::finish_pending_update();
+
   let spawner: updater::LocalSpawner<R> = updater::LocalSpawner::new();
   let plugins_manager: plugins::PluginSystem = plugins::PluginSystem::new();
 
@@ -32,6 +44,9 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
   builder = builder
     .manage(spawner)
     .manage(plugins_manager.clone())
+    .manage(utils::RunningGames::default())
+    .manage(bandwidth::BandwidthLimiter::default())
+    .manage(rpc::PluginSubscription::default())
     .setup(|app| {
       let config_file = "Sparus.json";
       let store_file_content;
@@ -71,6 +86,8 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
       }
 
       window.build()?;
+      #[cfg(desktop)]
+      launcher::confirm_update();
 
       if !store_file_destination.exists() {
         fs::create_dir_all(&app_data_dir).expect("Cannot create app data directory");
@@ -79,6 +96,18 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
       }
 
       let store = app.store("Sparus.json")?;
+      if let Some(bandwidth_json) = store.get(bandwidth::BANDWIDTH_KEY) {
+        // The store can be edited by hand, a bad limit must not keep the
+        // launcher from starting.
+        let config = serde_json::from_value::<bandwidth::BandwidthConfig>(bandwidth_json)
+          .map_err(errors::SparusError::from)
+          .and_then(|config| config.validate().map(|_| config));
+        match config {
+          Ok(config) => app.state::<bandwidth::BandwidthLimiter>().set(config),
+          Err(err) => eprintln!("Ignoring the bandwidth limit of the store: {err}"),
+        }
+      }
+
       let cms_url = match store.get("launcher_url") {
         Some(url_json) => {
           if let tauri_plugin_store::JsonValue::String(url_string) = url_json {
@@ -112,6 +141,8 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
         None => "kataster".to_string(),
       };
 
+      tauri::async_runtime::spawn(scheduler::start_update_scheduler(app.handle().clone()));
+
       tauri::async_runtime::spawn(rpc::start_rpc_client(
         app.handle().clone(),
         app_data_dir.clone(),
@@ -137,7 +168,6 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
   {
     builder = builder
       .plugin(tauri_plugin_dialog::init())
-      .plugin(tauri_plugin_shell::init())
       .plugin(tauri_plugin_process::init())
       .plugin(tauri_plugin_single_instance::init(|_, _, _| {}))
       .plugin(tauri_plugin_autostart::init(
@@ -149,11 +179,41 @@ pub fn run_app<R: Runtime>(mut builder: Builder<R>) {
   let app = builder
     .invoke_handler(tauri::generate_handler![
       updater::update_workspace,
+      bandwidth::get_bandwidth_limit,
+      bandwidth::set_bandwidth_limit,
       updater::update_available,
+      updater::cancel_update,
+      updater::pause_update,
+      updater::resume_update,
+      updater::set_game_channel,
+      updater::list_remote_versions,
+      updater::verify_workspace,
+      updater::repair_workspace,
+      launcher::launcher_update_available,
+      launcher::apply_launcher_update,
+      history::get_update_history,
+      library::list_games,
+      library::add_game,
+      library::remove_game,
+      library::pin_game_version,
+      library::uninstall_game,
       plugins::call_wasm_plugin_function,
+      plugins::describe_wasm_plugin,
+      plugins::get_disabled_plugins,
+      plugins::set_plugin_enabled,
+      quarantine::get_quarantined_plugins,
+      quarantine::clear_plugin_quarantine,
+      quarantine::report_plugin_load,
       plugins::js_plugins_path,
+      plugins::get_plugin_permissions,
+      plugins::approve_plugin_permissions,
+      settings::get_workspace_root,
+      settings::choose_workspace_root,
       utils::get_current_path,
-      utils::get_game_exe_name
+      utils::get_game_exe_name,
+      utils::get_installed_version,
+      utils::get_game_launch_config,
+      utils::launch_game
     ])
     .build(tauri::tauri_build_context!())
     .expect("error while building Sparus application");
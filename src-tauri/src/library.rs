@@ -0,0 +1,432 @@
+use crate::{
+  errors::SparusError,
+  settings,
+  updater::{self, LocalSpawner},
+  utils::RunningGames,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  env, fmt, fs, io,
+  path::{Component, Path, PathBuf},
+};
+use tauri::{command, AppHandle, Manager, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Store key holding the games installed through this launcher.
+const LIBRARY_KEY: &str = "library";
+/// Directory, inside the workspace root, of the game installed without a
+/// library entry, as before the library existed.
+pub const DEFAULT_GAME_DIR: &str = "game";
+/// Store keys describing the game of `DEFAULT_GAME_DIR`.
+const DEFAULT_GAME_KEYS: &[&str] = &["game_launch", "game_name", "initial_version"];
+
+/// A game of the library. How it is started is not part of it: the library
+/// lives in the store, which the webview can write, so games are started from
+/// the `launch.json` their repository ships.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Game {
+  pub id: String,
+  pub name: String,
+  pub repository_url: String,
+  pub workspace_path: String,
+  #[serde(default)]
+  pub auth: Option<GameAuth>,
+  #[serde(default)]
+  pub channel: Channel,
+  /// Repository of each non-stable channel. Stable uses `repository_url`.
+  #[serde(default)]
+  pub channels: HashMap<Channel, String>,
+  /// Version the player chose to stay on. Updates are not offered for a
+  /// pinned game, and updating it installs this version.
+  #[serde(default)]
+  pub pinned_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+  #[default]
+  Stable,
+  Beta,
+  Nightly,
+}
+
+impl Channel {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Channel::Stable => "stable",
+      Channel::Beta => "beta",
+      Channel::Nightly => "nightly",
+    }
+  }
+}
+
+impl fmt::Display for Channel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl Game {
+  pub fn repository_url(&self, channel: Channel) -> Result<&str, SparusError> {
+    match channel {
+      Channel::Stable => Ok(&self.repository_url),
+      _ => self
+        .channels
+        .get(&channel)
+        .map(String::as_str)
+        .ok_or(SparusError::Game(format!(
+          "Channel {channel} is not available for {}",
+          self.name
+        ))),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameAuth {
+  pub username: String,
+  pub password: String,
+}
+
+/// Where an update command works: the library game `game_id` when one is
+/// given, or else the explicit arguments, with the current directory as the
+/// default workspace like before the library existed.
+pub struct UpdateTarget {
+  pub game_id: Option<String>,
+  pub repository_url: String,
+  pub workspace_path: PathBuf,
+  pub auth: Option<(String, String)>,
+  pub channel: Channel,
+  pub pinned_version: Option<String>,
+}
+
+impl UpdateTarget {
+  pub fn resolve<R: Runtime>(
+    app: &AppHandle<R>,
+    game_id: Option<&str>,
+    repository_url: Option<String>,
+    workspace_path: Option<String>,
+    auth: Option<(String, String)>,
+  ) -> Result<Self, SparusError> {
+    match game_id {
+      Some(game_id) => {
+        let game = game(app, game_id)?;
+        // The library can be edited from the webview without `add_game`.
+        within_workspace_root(app, Path::new(&game.workspace_path))?;
+        Ok(Self {
+          game_id: Some(game.id.clone()),
+          repository_url: game.repository_url(game.channel)?.to_string(),
+          workspace_path: PathBuf::from(game.workspace_path),
+          auth: game.auth.map(|auth| (auth.username, auth.password)),
+          channel: game.channel,
+          pinned_version: game.pinned_version,
+        })
+      }
+      None => Ok(Self {
+        game_id: None,
+        repository_url: repository_url.ok_or(SparusError::Update(
+          "No repository url was provided".to_string(),
+        ))?,
+        workspace_path: match workspace_path {
+          Some(workspace_path) => PathBuf::from(workspace_path),
+          None => env::current_dir()?,
+        },
+        auth,
+        channel: Channel::Stable,
+        pinned_version: None,
+      }),
+    }
+  }
+
+  pub fn auth(&self) -> Option<(&str, &str)> {
+    self
+      .auth
+      .as_ref()
+      .map(|(username, password)| (username.as_str(), password.as_str()))
+  }
+}
+
+/// The game installed in `DEFAULT_GAME_DIR` without a library entry, from the
+/// repository the frontend installs it from. `None` when it isn't installed.
+pub fn default_game_target<R: Runtime>(
+  app: &AppHandle<R>,
+) -> Result<Option<UpdateTarget>, SparusError> {
+  let workspace_path = match workspace_root(app) {
+    Ok(root) => root.join(DEFAULT_GAME_DIR),
+    Err(SparusError::NoWorkspaceRoot) => return Ok(None),
+    Err(err) => return Err(err),
+  };
+  if !workspace_path.join(".update").join("state.json").is_file() {
+    return Ok(None);
+  }
+  let store = app.store("Sparus.json")?;
+  let Some(tauri_plugin_store::JsonValue::String(repository_url)) = store.get("repository_url")
+  else {
+    return Ok(None);
+  };
+  let repository_name = match store.get("repository_name") {
+    Some(tauri_plugin_store::JsonValue::String(repository_name)) => repository_name,
+    _ => String::new(),
+  };
+  let platform = match (env::consts::OS, env::consts::ARCH) {
+    ("windows", _) => "win64",
+    ("linux", _) => "linux",
+    ("macos", "aarch64") => "macos_arm64",
+    ("macos", _) => "macos_x86_64",
+    _ => "",
+  };
+  UpdateTarget::resolve(
+    app,
+    None,
+    Some(format!(
+      "{repository_url}/{repository_name}/game/{platform}/"
+    )),
+    Some(workspace_path.display().to_string()),
+    None,
+  )
+  .map(Some)
+}
+
+pub fn games<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<Game>, SparusError> {
+  let store = app.store("Sparus.json")?;
+  match store.get(LIBRARY_KEY) {
+    Some(library_json) => Ok(serde_json::from_value(library_json)?),
+    None => Ok(Vec::new()),
+  }
+}
+
+pub fn game<R: Runtime>(app: &AppHandle<R>, game_id: &str) -> Result<Game, SparusError> {
+  games(app)?
+    .into_iter()
+    .find(|game| game.id == game_id)
+    .ok_or(SparusError::GameNotFound(game_id.to_string()))
+}
+
+pub fn save_games<R: Runtime>(app: &AppHandle<R>, games: &[Game]) -> Result<(), SparusError> {
+  let store = app.store("Sparus.json")?;
+  store.set(LIBRARY_KEY, serde_json::to_value(games)?);
+  store.save()?;
+  Ok(())
+}
+
+#[command]
+pub fn list_games<R: Runtime>(app: AppHandle<R>) -> Result<Vec<Game>, SparusError> {
+  games(&app)
+}
+
+/// Adds `game` to the library. Its workspace must be strictly inside the
+/// workspace root, and its id not taken yet.
+#[command]
+pub fn add_game<R: Runtime>(app: AppHandle<R>, game: Game) -> Result<(), SparusError> {
+  if game.id.trim().is_empty() {
+    return Err(SparusError::Game("A game needs an id".to_string()));
+  }
+  if game.repository_url.trim().is_empty()
+    || game.channels.values().any(|url| url.trim().is_empty())
+  {
+    return Err(SparusError::Game(format!(
+      "Game {} needs a repository url",
+      game.id
+    )));
+  }
+  within_workspace_root(&app, Path::new(&game.workspace_path))?;
+
+  let mut games = games(&app)?;
+  if games.iter().any(|existing| existing.id == game.id) {
+    return Err(SparusError::Game(format!(
+      "Game {} is already in the library",
+      game.id
+    )));
+  }
+  games.push(game);
+  save_games(&app, &games)
+}
+
+#[command]
+pub fn remove_game<R: Runtime>(app: AppHandle<R>, game_id: String) -> Result<(), SparusError> {
+  let mut games = games(&app)?;
+  let count = games.len();
+  games.retain(|game| game.id != game_id);
+  if games.len() == count {
+    return Err(SparusError::GameNotFound(game_id));
+  }
+  save_games(&app, &games)
+}
+
+/// Pins a game to `version`, or unpins it when `version` is `None`.
+///
+/// This only records the choice. The next `update_workspace` on the game
+/// installs the pinned version, whether it is older or newer than the
+/// installed one. The version must be one the repository of the game
+/// publishes.
+#[command]
+pub async fn pin_game_version<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: String,
+  version: Option<String>,
+) -> Result<(), SparusError> {
+  if let Some(version) = &version {
+    let target = UpdateTarget::resolve(&app, Some(&game_id), None, None, None)?;
+    let published = updater::remote_versions(&target)
+      .await?
+      .iter()
+      .any(|remote| remote.version == *version);
+    if !published {
+      return Err(SparusError::Game(format!(
+        "{version} is not a published version of {game_id}"
+      )));
+    }
+  }
+
+  let mut games = games(&app)?;
+  let game = games
+    .iter_mut()
+    .find(|game| game.id == game_id)
+    .ok_or(SparusError::GameNotFound(game_id))?;
+  game.pinned_version = version;
+  save_games(&app, &games)
+}
+
+/// Deletes the workspace of a game, `.update` state included, and clears the
+/// store keys describing it. Returns the number of bytes freed.
+///
+/// Only a directory strictly inside the workspace root can be deleted. A bad
+/// library entry or argument must never be able to wipe an arbitrary folder,
+/// or the launcher's own directory. A workspace with a running game, or an
+/// update in progress or paused, is left alone.
+#[command]
+pub async fn uninstall_game<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: Option<String>,
+  workspace_path: Option<String>,
+) -> Result<u64, SparusError> {
+  let workspace_path = match game_id {
+    Some(ref game_id) => PathBuf::from(game(&app, game_id)?.workspace_path),
+    None => PathBuf::from(workspace_path.ok_or(SparusError::Game(
+      "No workspace path was provided".to_string(),
+    ))?),
+  };
+
+  let mut games = games(&app)?;
+  let count = games.len();
+  if let Some(ref game_id) = game_id {
+    games.retain(|game| game.id != *game_id);
+  }
+
+  let mut is_default_game = false;
+  let freed_bytes = if workspace_path.exists() {
+    let workspace = inside_workspace_root(&app, &workspace_path)?;
+    if app.state::<RunningGames>().any_running_in(&workspace) {
+      return Err(SparusError::Game(
+        "The game is running, close it before uninstalling".to_string(),
+      ));
+    }
+    let spawner = app.state::<LocalSpawner<R>>();
+    if let Some(update_id) = updater::workspace_update(&spawner, workspace.clone()).await {
+      return Err(SparusError::Game(format!(
+        "Update {update_id} is in progress, cancel it before uninstalling"
+      )));
+    }
+
+    // Other library entries sharing the workspace would point at nothing.
+    games.retain(|game| {
+      Path::new(&game.workspace_path)
+        .canonicalize()
+        .map_or(true, |path| path != workspace)
+    });
+    is_default_game = workspace_root(&app)?
+      .join(DEFAULT_GAME_DIR)
+      .canonicalize()
+      .is_ok_and(|path| path == workspace);
+
+    let freed_bytes = dir_size(&workspace)?;
+    fs::remove_dir_all(&workspace)?;
+    freed_bytes
+  } else {
+    0
+  };
+
+  if games.len() != count {
+    save_games(&app, &games)?;
+  }
+  // These keys describe the game installed without a library entry.
+  if is_default_game {
+    let store = app.store("Sparus.json")?;
+    for key in DEFAULT_GAME_KEYS {
+      store.delete(key);
+    }
+    store.save()?;
+  }
+  Ok(freed_bytes)
+}
+
+/// Directory the games are installed in, as the user chose it through
+/// `choose_workspace_root`. It is never read from the store, which the webview
+/// can write, and there is no default: everything under it can be deleted.
+pub fn workspace_root<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, SparusError> {
+  settings::load(app)?
+    .workspace_root
+    .ok_or(SparusError::NoWorkspaceRoot)
+}
+
+/// Canonical form of `path`, an existing directory, when it is strictly inside
+/// the workspace root. Deleting or running anything outside of it must never
+/// be possible, whatever the library or the webview says.
+pub fn inside_workspace_root<R: Runtime>(
+  app: &AppHandle<R>,
+  path: &Path,
+) -> Result<PathBuf, SparusError> {
+  let canonical = path.canonicalize()?;
+  let root = workspace_root(app)?.canonicalize()?;
+  if canonical == root || !canonical.starts_with(&root) {
+    return Err(SparusError::OutsideWorkspaceRoot(
+      path.display().to_string(),
+    ));
+  }
+  Ok(canonical)
+}
+
+/// `path`, an absolute path that may not exist yet, when it is strictly inside
+/// the workspace root. Its closest existing ancestor is made canonical, so that
+/// a symlink can't lead out of the root.
+pub fn within_workspace_root<R: Runtime>(
+  app: &AppHandle<R>,
+  path: &Path,
+) -> Result<PathBuf, SparusError> {
+  let outside = || SparusError::OutsideWorkspaceRoot(path.display().to_string());
+  if !path.is_absolute()
+    || path
+      .components()
+      .any(|component| matches!(component, Component::ParentDir | Component::CurDir))
+  {
+    return Err(outside());
+  }
+  let ancestor = path
+    .ancestors()
+    .find(|ancestor| ancestor.exists())
+    .ok_or_else(outside)?;
+  let resolved = ancestor.canonicalize()?.join(path.strip_prefix(ancestor)?);
+  let root = workspace_root(app)?.canonicalize()?;
+  if resolved == root || !resolved.starts_with(&root) {
+    return Err(outside());
+  }
+  Ok(resolved)
+}
+
+/// Total size of the files under `dir`. Symlinks are counted as links, not
+/// followed, since deleting the directory doesn't touch their targets.
+fn dir_size(dir: &Path) -> io::Result<u64> {
+  let mut size = 0;
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let meta = entry.path().symlink_metadata()?;
+    if meta.is_dir() {
+      size += dir_size(&entry.path())?;
+    } else {
+      size += meta.len();
+    }
+  }
+  Ok(size)
+}
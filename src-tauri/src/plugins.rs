@@ -1,17 +1,608 @@
-use crate::errors::SparusError;
-use serde_json::Value;
-use std::path::PathBuf;
+use crate::{
+  errors::SparusError,
+  hooks::{Hook, HOOKS_INTERFACE},
+  host::{self, Launcher},
+  library, quarantine, rpc,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  env,
+  future::Future,
+  io::ErrorKind,
+  path::{self, Path, PathBuf},
+  sync::{Arc, Mutex as StdMutex},
+  thread,
+  time::Duration,
+};
 use tauri::{command, AppHandle, Manager, Runtime, State};
-use tokio::fs;
+#[cfg(desktop)]
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use tauri_plugin_store::StoreExt;
+#[cfg(desktop)]
+use tokio::sync::oneshot;
+use tokio::{
+  fs,
+  sync::Mutex,
+  time::{error::Elapsed, timeout},
+};
 use wasmtime::{
-  component::{types::ComponentItem, Component, Linker, ResourceTable, Type, Val},
-  Config, Engine, Result, Store,
+  component::{
+    types::ComponentItem, Component, ComponentExportIndex, HasSelf, Instance, Linker,
+    ResourceTable, Type, Val,
+  },
+  Config, Engine, ResourceLimiter, Result, Store, StoreLimits, StoreLimitsBuilder, Trap,
 };
-use wasmtime_wasi::{WasiCtx, WasiCtxView, WasiView};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtx, WasiCtxView, WasiView};
+use wasmtime_wasi_http::{
+  bindings::http::types::ErrorCode,
+  body::HyperOutgoingBody,
+  types::{default_send_request, HostFutureIncomingResponse, OutgoingRequestConfig},
+  HttpResult, WasiHttpCtx, WasiHttpView,
+};
+
+/// File name of the manifest shipped in the directory of every plugin.
+pub const MANIFEST_FILE: &str = "plugin.json";
+/// File of the app data dir holding the capabilities the user approved. It is
+/// only written by the launcher, after the user confirmed in a native dialog:
+/// the webview can write the store, so approvals can't live there.
+const PLUGIN_PERMISSIONS_FILE: &str = "plugin_permissions.json";
+/// Store key holding the names of the plugins the user disabled.
+const DISABLED_PLUGINS_KEY: &str = "disabled_plugins";
+/// Store key holding the `PluginLimits` of the plugins that don't use the
+/// defaults, by plugin name.
+const PLUGIN_LIMITS_KEY: &str = "plugin_limits";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+/// How often the epoch of the engine advances, which is the precision of the
+/// call timeouts.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// `plugin.json`, which describes a plugin to the launcher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginManifest {
+  pub name: String,
+  pub version: Version,
+  #[serde(default)]
+  pub description: String,
+  /// Oldest launcher version the plugin works with.
+  #[serde(default)]
+  pub min_launcher_version: Option<Version>,
+  /// Functions of the `.wasm` the frontend can call. Nothing else is callable
+  /// through `call_wasm_plugin_function`.
+  #[serde(default)]
+  pub exports: Vec<String>,
+  #[serde(default)]
+  pub capabilities: PluginCapabilities,
+  /// Keep one instance of the plugin, and its state, across calls.
+  #[serde(default)]
+  pub persistent: bool,
+  /// Launcher events the plugin exports a `sparus:launcher/hooks` function
+  /// for.
+  #[serde(default)]
+  pub hooks: Vec<Hook>,
+}
+
+/// What a plugin asks to access from the host, beyond its own sandbox.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginCapabilities {
+  /// Host directories to mount in the plugin's filesystem.
+  #[serde(default)]
+  pub preopens: Vec<Preopen>,
+  /// Environment variables to pass to the plugin.
+  #[serde(default)]
+  pub env: Vec<String>,
+  /// Hosts the plugin makes HTTP requests to.
+  #[serde(default)]
+  pub http: Vec<String>,
+  /// Imports of `sparus:launcher/host` the plugin calls.
+  #[serde(default)]
+  pub host: Vec<HostAccess>,
+}
+
+/// A group of `sparus:launcher/host` imports. The imports of a group that
+/// wasn't approved return an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostAccess {
+  /// `installed-version` and `workspace-path`.
+  Games,
+  /// `start-update`.
+  Updates,
+  /// `notify`.
+  Notifications,
+  /// `emit`.
+  Events,
+}
+
+impl HostAccess {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      HostAccess::Games => "games",
+      HostAccess::Updates => "updates",
+      HostAccess::Notifications => "notifications",
+      HostAccess::Events => "events",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preopen {
+  /// A directory private to the plugin, mounted read-write at `/data`. It is
+  /// kept when the plugin is updated or removed.
+  Data,
+  /// The directory the games are installed in, mounted read-only at `/games`.
+  Games,
+}
+
+impl PluginCapabilities {
+  /// The capabilities present in both `self` and `other`.
+  fn intersection(&self, other: &Self) -> Self {
+    Self {
+      preopens: self
+        .preopens
+        .iter()
+        .filter(|preopen| other.preopens.contains(preopen))
+        .copied()
+        .collect(),
+      env: self
+        .env
+        .iter()
+        .filter(|name| other.env.contains(name))
+        .cloned()
+        .collect(),
+      http: self
+        .http
+        .iter()
+        .filter(|host| {
+          other
+            .http
+            .iter()
+            .any(|other| other.eq_ignore_ascii_case(host))
+        })
+        .cloned()
+        .collect(),
+      host: self
+        .host
+        .iter()
+        .filter(|access| other.host.contains(access))
+        .copied()
+        .collect(),
+    }
+  }
+
+  /// What each capability lets the plugin do, as shown to the user.
+  fn descriptions(&self) -> Vec<String> {
+    let mut descriptions: Vec<String> = self
+      .preopens
+      .iter()
+      .map(|preopen| match preopen {
+        Preopen::Data => "store files in its data directory".to_string(),
+        Preopen::Games => "read the games directory".to_string(),
+      })
+      .collect();
+    descriptions.extend(
+      self
+        .env
+        .iter()
+        .map(|name| format!("read the environment variable {name}")),
+    );
+    descriptions.extend(
+      self
+        .http
+        .iter()
+        .map(|host| format!("send requests to {host}")),
+    );
+    descriptions.extend(self.host.iter().map(|access| {
+      match access {
+        HostAccess::Games => "see where games are installed and their versions",
+        HostAccess::Updates => "start game updates",
+        HostAccess::Notifications => "show notifications",
+        HostAccess::Events => "send events to the launcher window",
+      }
+      .to_string()
+    }));
+    descriptions
+  }
+}
+
+/// Fails with `SparusError::InvalidPluginName` unless `plugin` can name a
+/// directory of `plugins` and `plugin_data`, and nothing outside of them.
+pub fn check_plugin_name(plugin: &str) -> Result<(), SparusError> {
+  let mut components = Path::new(plugin).components();
+  let single_component = matches!(
+    (components.next(), components.next()),
+    (Some(path::Component::Normal(_)), None)
+  );
+  if !single_component || plugin.contains(['/', '\\']) || plugin.contains("..") {
+    return Err(SparusError::InvalidPluginName(plugin.to_string()));
+  }
+  Ok(())
+}
+
+impl PluginManifest {
+  pub fn is_compatible(&self, launcher_version: &Version) -> bool {
+    self
+      .min_launcher_version
+      .as_ref()
+      .is_none_or(|min_version| min_version <= launcher_version)
+  }
+}
+
+/// Reads the manifest of the plugin installed in `plugin_dir`. Plugins
+/// installed before manifests existed have none.
+pub async fn read_manifest(plugin_dir: &Path) -> Result<Option<PluginManifest>, SparusError> {
+  match fs::read(plugin_dir.join(MANIFEST_FILE)).await {
+    Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+    Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Capabilities a plugin requested in its manifest, and those the user
+/// approved. A plugin only gets the capabilities present in both.
+#[derive(Serialize)]
+pub struct PluginPermissions {
+  requested: PluginCapabilities,
+  approved: PluginCapabilities,
+}
+
+/// An entry of `PLUGIN_PERMISSIONS_FILE`. The file is a list of them rather
+/// than a map, so that the store plugin, which only reads and writes objects,
+/// can't be pointed at it.
+#[derive(Serialize, Deserialize)]
+struct ApprovedCapabilities {
+  plugin: String,
+  capabilities: PluginCapabilities,
+}
+
+async fn approved_capabilities<R: Runtime>(
+  app: &AppHandle<R>,
+) -> Result<HashMap<String, PluginCapabilities>, SparusError> {
+  let path = app.path().app_data_dir()?.join(PLUGIN_PERMISSIONS_FILE);
+  let approvals: Vec<ApprovedCapabilities> = match fs::read(&path).await {
+    Ok(content) => serde_json::from_slice(&content)?,
+    Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+    Err(err) => return Err(err.into()),
+  };
+  Ok(
+    approvals
+      .into_iter()
+      .map(|approval| (approval.plugin, approval.capabilities))
+      .collect(),
+  )
+}
+
+async fn save_approved_capabilities<R: Runtime>(
+  app: &AppHandle<R>,
+  permissions: HashMap<String, PluginCapabilities>,
+) -> Result<(), SparusError> {
+  let app_data_dir = app.path().app_data_dir()?;
+  fs::create_dir_all(&app_data_dir).await?;
+  let approvals: Vec<ApprovedCapabilities> = permissions
+    .into_iter()
+    .map(|(plugin, capabilities)| ApprovedCapabilities {
+      plugin,
+      capabilities,
+    })
+    .collect();
+  // Written aside and renamed, so that a crash can't leave a truncated file.
+  let path = app_data_dir.join(PLUGIN_PERMISSIONS_FILE);
+  let part = path.with_extension("json.part");
+  fs::write(&part, serde_json::to_vec_pretty(&approvals)?).await?;
+  fs::rename(part, path).await?;
+  Ok(())
+}
+
+/// Asks the user, in a native dialog the webview can't answer, to let
+/// `plugin` do what `capabilities` allow.
+#[cfg(desktop)]
+async fn confirm_capabilities<R: Runtime>(
+  app: &AppHandle<R>,
+  plugin: &str,
+  capabilities: PluginCapabilities,
+) -> Result<bool, SparusError> {
+  let message = format!(
+    "Allow the plugin {plugin} to:\n{}",
+    capabilities
+      .descriptions()
+      .iter()
+      .map(|description| format!("- {description}"))
+      .collect::<Vec<_>>()
+      .join("\n")
+  );
+  let (sender, receiver) = oneshot::channel();
+  app
+    .dialog()
+    .message(message)
+    .title("Plugin permissions")
+    .kind(MessageDialogKind::Warning)
+    .buttons(MessageDialogButtons::OkCancelCustom(
+      "Allow".to_string(),
+      "Deny".to_string(),
+    ))
+    .show(move |allowed| {
+      let _ = sender.send(allowed);
+    });
+  Ok(receiver.await.unwrap_or(false))
+}
+
+/// There is no native dialog to confirm capabilities in, and the webview
+/// can't be trusted to, so none can be granted.
+#[cfg(mobile)]
+async fn confirm_capabilities<R: Runtime>(
+  _app: &AppHandle<R>,
+  plugin: &str,
+  _capabilities: PluginCapabilities,
+) -> Result<bool, SparusError> {
+  Err(SparusError::PluginInternal(format!(
+    "The permissions of plugin {plugin} can't be confirmed on this platform"
+  )))
+}
+
+/// The capabilities of `plugin` approved once `capabilities` are, out of the
+/// `current` permissions. `confirm` is asked about them when they grant
+/// anything new, and nothing is approved unless it accepts.
+async fn approve_capabilities<F, Fut>(
+  plugin: &str,
+  current: &PluginPermissions,
+  capabilities: &PluginCapabilities,
+  confirm: F,
+) -> Result<PluginCapabilities, SparusError>
+where
+  F: FnOnce(PluginCapabilities) -> Fut,
+  Fut: Future<Output = Result<bool, SparusError>>,
+{
+  let approved = current.requested.intersection(capabilities);
+  let already_approved = current.approved.descriptions();
+  let granted = approved
+    .descriptions()
+    .iter()
+    .any(|description| !already_approved.contains(description));
+  if granted && !confirm(approved.clone()).await? {
+    return Err(SparusError::PermissionsDeclined(plugin.to_string()));
+  }
+  Ok(approved)
+}
+
+async fn plugin_permissions<R: Runtime>(
+  app: &AppHandle<R>,
+  plugin: &str,
+) -> Result<PluginPermissions, SparusError> {
+  check_plugin_name(plugin)?;
+  let plugin_dir = app.path().app_data_dir()?.join("plugins").join(plugin);
+  let requested = read_manifest(&plugin_dir)
+    .await?
+    .map(|manifest| manifest.capabilities)
+    .unwrap_or_default();
+  let approved = approved_capabilities(app)
+    .await?
+    .remove(plugin)
+    .unwrap_or_default();
+  Ok(PluginPermissions {
+    requested,
+    approved,
+  })
+}
+
+#[command]
+pub async fn get_plugin_permissions<R: Runtime>(
+  app: AppHandle<R>,
+  plugin: String,
+) -> Result<PluginPermissions, SparusError> {
+  plugin_permissions(&app, &plugin).await
+}
+
+/// Records the capabilities the user approved for `plugin`. Only capabilities
+/// requested by its manifest are kept, so approving can't give a plugin more
+/// than it declared.
+///
+/// Any script of the webview can call this, so the user confirms every
+/// capability that wasn't approved yet in a native dialog. Withdrawing
+/// capabilities needs no confirmation. Where there is no native dialog, no
+/// capability can be granted.
+#[command]
+pub async fn approve_plugin_permissions<R: Runtime>(
+  app: AppHandle<R>,
+  plugin: String,
+  capabilities: PluginCapabilities,
+) -> Result<PluginPermissions, SparusError> {
+  let current = plugin_permissions(&app, &plugin).await?;
+  let approved = approve_capabilities(&plugin, &current, &capabilities, |approved| {
+    confirm_capabilities(&app, &plugin, approved)
+  })
+  .await?;
+
+  let mut permissions = approved_capabilities(&app).await?;
+  permissions.insert(plugin.clone(), approved);
+  save_approved_capabilities(&app, permissions).await?;
+  // A long-lived instance keeps the sandbox it was created with.
+  app.state::<PluginSystem>().invalidate(&plugin);
+
+  plugin_permissions(&app, &plugin).await
+}
+
+/// Plugins the user disabled. They stay installed, but their `frontend.js`
+/// isn't loaded and their `.wasm` isn't called.
+pub fn disabled_plugins<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<String>, SparusError> {
+  let store = app.store("Sparus.json")?;
+  match store.get(DISABLED_PLUGINS_KEY) {
+    Some(disabled_json) => Ok(serde_json::from_value(disabled_json)?),
+    None => Ok(Vec::new()),
+  }
+}
+
+#[command]
+pub fn get_disabled_plugins<R: Runtime>(app: AppHandle<R>) -> Result<Vec<String>, SparusError> {
+  disabled_plugins(&app)
+}
+
+/// Enables or disables a plugin without uninstalling it. The launcher
+/// subscribes to the CMS again to tell it about the change.
+#[command]
+pub fn set_plugin_enabled<R: Runtime>(
+  app: AppHandle<R>,
+  plugin: String,
+  enabled: bool,
+) -> Result<(), SparusError> {
+  check_plugin_name(&plugin)?;
+  let mut disabled = disabled_plugins(&app)?;
+  disabled.retain(|name| *name != plugin);
+  if !enabled {
+    disabled.push(plugin.clone());
+  }
+  let store = app.store("Sparus.json")?;
+  store.set(DISABLED_PLUGINS_KEY, serde_json::to_value(&disabled)?);
+  store.save()?;
+  // Also drops the state of a disabled plugin's long-lived instance.
+  app.state::<PluginSystem>().invalidate(&plugin);
+  app.state::<rpc::PluginSubscription>().resubscribe();
+  Ok(())
+}
+
+/// What a plugin call can access on the host: the capabilities that were both
+/// requested and approved, resolved to host paths and values.
+#[derive(Default)]
+pub struct Sandbox {
+  preopens: Vec<(PathBuf, &'static str, DirPerms, FilePerms)>,
+  env: Vec<(String, String)>,
+  http: Vec<String>,
+  host: Vec<HostAccess>,
+}
+
+impl Sandbox {
+  pub async fn for_plugin<R: Runtime>(
+    app: &AppHandle<R>,
+    plugin: &str,
+  ) -> Result<Self, SparusError> {
+    let permissions = plugin_permissions(app, plugin).await?;
+    let granted = permissions.requested.intersection(&permissions.approved);
+
+    let mut sandbox = Sandbox::default();
+    for preopen in granted.preopens {
+      match preopen {
+        Preopen::Data => {
+          let data_dir = app.path().app_data_dir()?.join("plugin_data").join(plugin);
+          fs::create_dir_all(&data_dir).await?;
+          sandbox
+            .preopens
+            .push((data_dir, "/data", DirPerms::all(), FilePerms::all()));
+        }
+        Preopen::Games => sandbox.preopens.push((
+          library::workspace_root(app)?,
+          "/games",
+          DirPerms::READ,
+          FilePerms::READ,
+        )),
+      }
+    }
+    // Variables that aren't set on the host are left unset for the plugin.
+    sandbox.env = granted
+      .env
+      .into_iter()
+      .filter_map(|name| env::var(&name).ok().map(|value| (name, value)))
+      .collect();
+    sandbox.http = granted.http;
+    sandbox.host = granted.host;
+    Ok(sandbox)
+  }
+}
+
+/// How long a call to a plugin may run, and how much memory it may use.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginLimits {
+  #[serde(default)]
+  pub timeout_ms: Option<u64>,
+  #[serde(default)]
+  pub memory_bytes: Option<usize>,
+}
+
+impl PluginLimits {
+  pub fn for_plugin<R: Runtime>(app: &AppHandle<R>, plugin: &str) -> Result<Self, SparusError> {
+    let store = app.store("Sparus.json")?;
+    let Some(limits_json) = store.get(PLUGIN_LIMITS_KEY) else {
+      return Ok(Self::default());
+    };
+    let mut limits: HashMap<String, PluginLimits> = serde_json::from_value(limits_json)?;
+    Ok(limits.remove(plugin).unwrap_or_default())
+  }
+
+  fn timeout(&self) -> Duration {
+    self
+      .timeout_ms
+      .map(Duration::from_millis)
+      .unwrap_or(DEFAULT_TIMEOUT)
+  }
+
+  fn memory_bytes(&self) -> usize {
+    self.memory_bytes.unwrap_or(DEFAULT_MEMORY_BYTES)
+  }
+}
+
+/// `StoreLimits`, remembering whether a limit was hit so that the trap it
+/// causes can be told apart from a bug in the plugin.
+struct PluginLimiter {
+  limits: StoreLimits,
+  exceeded: bool,
+}
+
+impl ResourceLimiter for PluginLimiter {
+  fn memory_growing(
+    &mut self,
+    current: usize,
+    desired: usize,
+    maximum: Option<usize>,
+  ) -> Result<bool> {
+    let allowed = self.limits.memory_growing(current, desired, maximum);
+    self.exceeded |= !matches!(allowed, Ok(true));
+    allowed
+  }
+
+  fn table_growing(
+    &mut self,
+    current: usize,
+    desired: usize,
+    maximum: Option<usize>,
+  ) -> Result<bool> {
+    let allowed = self.limits.table_growing(current, desired, maximum);
+    self.exceeded |= !matches!(allowed, Ok(true));
+    allowed
+  }
+}
 
 pub struct ComponentRunStates {
   pub wasi_ctx: WasiCtx,
   pub resource_table: ResourceTable,
+  pub http_ctx: WasiHttpCtx,
+  /// Hosts the plugin may send HTTP requests to.
+  pub http_hosts: Vec<String>,
+  limiter: PluginLimiter,
+  /// Backs the `sparus:launcher/host` imports.
+  pub launcher: Arc<dyn Launcher>,
+  /// Groups of `sparus:launcher/host` imports the plugin may call.
+  pub host_access: Vec<HostAccess>,
+  pub plugin_name: String,
+}
+
+impl ComponentRunStates {
+  /// Fails unless the plugin was granted `access`, with the error returned to
+  /// the plugin.
+  pub fn check_access(&self, access: HostAccess) -> Result<(), String> {
+    if self.host_access.contains(&access) {
+      Ok(())
+    } else {
+      Err(format!(
+        "Plugin {} wasn't granted the `{}` host capability",
+        self.plugin_name,
+        access.as_str()
+      ))
+    }
+  }
 }
 
 impl WasiView for ComponentRunStates {
@@ -23,114 +614,594 @@ impl WasiView for ComponentRunStates {
   }
 }
 
+impl WasiHttpView for ComponentRunStates {
+  fn ctx(&mut self) -> &mut WasiHttpCtx {
+    &mut self.http_ctx
+  }
+
+  fn table(&mut self) -> &mut ResourceTable {
+    &mut self.resource_table
+  }
+
+  fn send_request(
+    &mut self,
+    request: hyper::Request<HyperOutgoingBody>,
+    config: OutgoingRequestConfig,
+  ) -> HttpResult<HostFutureIncomingResponse> {
+    let host = request.uri().host().unwrap_or_default();
+    if !self
+      .http_hosts
+      .iter()
+      .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+      return Err(ErrorCode::HttpRequestDenied.into());
+    }
+    Ok(default_send_request(request, config))
+  }
+}
+
+/// A plugin instantiated in its own `Store`, along with the limits it runs
+/// under.
+struct PluginInstance {
+  hash: String,
+  store: Store<ComponentRunStates>,
+  instance: Instance,
+  /// The exported interface functions are looked up in, unless the call
+  /// names another one. A plugin that only exports hooks has none.
+  interface: Option<ComponentExportIndex>,
+  limits: PluginLimits,
+}
+
+impl PluginInstance {
+  async fn call(
+    &mut self,
+    plugin_name: &str,
+    interface: Option<&str>,
+    function: &str,
+    args: &[Value],
+  ) -> Result<Value, SparusError> {
+    let interface = match interface {
+      Some(name) => self
+        .instance
+        .get_export_index(&mut self.store, None, name)
+        .ok_or(SparusError::PluginInternal(format!(
+          "Plugin {plugin_name} doesn't export {name}"
+        )))?,
+      None => self.interface.ok_or(SparusError::PluginInternal(
+        "instance index not found".to_string(),
+      ))?,
+    };
+    let call_timeout = self.limits.timeout();
+    self.store.set_epoch_deadline(epoch_ticks(call_timeout));
+    let result = timeout(
+      call_timeout,
+      Self::run(&mut self.store, &self.instance, &interface, function, args),
+    )
+    .await;
+    check_limits(
+      result,
+      &self.store,
+      &self.limits,
+      format!("Plugin {plugin_name}: {function}"),
+    )
+  }
+
+  async fn run(
+    store: &mut Store<ComponentRunStates>,
+    instance: &Instance,
+    interface: &ComponentExportIndex,
+    function: &str,
+    args: &[Value],
+  ) -> Result<Value, SparusError> {
+    let func_index = instance
+      .get_export_index(&mut *store, Some(interface), function)
+      .ok_or(SparusError::PluginInternal(
+        "function index not found".to_string(),
+      ))?;
+
+    let func = instance
+      .get_func(&mut *store, func_index)
+      .ok_or(SparusError::PluginInternal(
+        "function not found".to_string(),
+      ))?;
+
+    let func_ty = func.ty(&*store);
+    if args.len() != func_ty.params().len() {
+      return Err(SparusError::PluginValue(format!(
+        "{function} expects {} arguments, got {}",
+        func_ty.params().len(),
+        args.len()
+      )));
+    }
+    let args = func_ty
+      .params()
+      .zip(args)
+      .map(|((name, ty), value)| json_to_val(value, &ty, name))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    // Placeholders, overwritten by the call.
+    let mut results = vec![Val::Bool(false); func_ty.results().len()];
+    if func_ty.async_() {
+      store
+        .run_concurrent(async |accessor| -> Result<(), SparusError> {
+          func.call_concurrent(accessor, &args, &mut results).await?;
+          Ok(())
+        })
+        .await??
+    } else {
+      func.call_async(&mut *store, &args, &mut results).await?
+    }
+
+    results_to_json(results)
+  }
+}
+
+fn epoch_ticks(duration: Duration) -> u64 {
+  (duration.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+}
+
+/// Turns the failure of a plugin that ran into its limits into a
+/// `SparusError::PluginLimit`. `what` names what was running.
+///
+/// The epoch deadline interrupts a plugin stuck in its own code, while the
+/// timeout also covers a plugin waiting on the host, an HTTP request for
+/// instance.
+fn check_limits<T>(
+  result: Result<Result<T, SparusError>, Elapsed>,
+  store: &Store<ComponentRunStates>,
+  limits: &PluginLimits,
+  what: String,
+) -> Result<T, SparusError> {
+  let timed_out = || {
+    SparusError::PluginLimit(format!(
+      "{what} did not return within {:?}",
+      limits.timeout()
+    ))
+  };
+  match result {
+    Err(_) => Err(timed_out()),
+    Ok(Err(SparusError::Wasmtime(err))) if err.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => {
+      Err(timed_out())
+    }
+    Ok(Err(_)) if store.data().limiter.exceeded => Err(SparusError::PluginLimit(format!(
+      "{what} exceeded its memory limit of {} bytes",
+      limits.memory_bytes()
+    ))),
+    Ok(result) => result,
+  }
+}
+
+/// The interface calls go to when they don't name one: the last exported
+/// interface, leaving the hooks out since only the launcher calls them.
+fn default_interface(engine: &Engine, component: &Component) -> Option<String> {
+  component
+    .component_type()
+    .exports(engine)
+    .filter(|(name, export_type)| {
+      matches!(export_type.ty, ComponentItem::ComponentInstance(_)) && *name != HOOKS_INTERFACE
+    })
+    .map(|(name, _)| name.to_string())
+    .last()
+}
+
+#[derive(Debug, Serialize)]
+pub struct InterfaceDescription {
+  pub name: String,
+  /// Whether calls that don't name an interface go to this one.
+  pub default: bool,
+  pub functions: Vec<FunctionDescription>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FunctionDescription {
+  pub name: String,
+  pub params: Vec<ParamDescription>,
+  pub results: Vec<Value>,
+  #[serde(rename = "async")]
+  pub is_async: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParamDescription {
+  pub name: String,
+  #[serde(rename = "type")]
+  pub ty: Value,
+}
+
 #[derive(Clone)]
 pub struct PluginSystem {
   engine: Engine,
+  /// Compiled components, with the hash of the `.wasm` they were compiled
+  /// from, by plugin name.
+  components: Arc<StdMutex<HashMap<String, (String, Component)>>>,
+  /// Long-lived instances of the plugins whose manifest asks for one.
+  instances: Arc<StdMutex<HashMap<String, Arc<Mutex<PluginInstance>>>>>,
 }
 
 impl PluginSystem {
   pub fn new() -> Self {
     let mut config = Config::new();
     config.wasm_component_model_async(true);
+    config.epoch_interruption(true);
     let engine =
       Engine::new(&config).unwrap_or_else(|err| panic!("Unable to start wasm runtime: {}", err));
-    Self { engine }
+
+    let ticker = engine.clone();
+    thread::spawn(move || loop {
+      thread::sleep(EPOCH_TICK);
+      ticker.increment_epoch();
+    });
+
+    Self {
+      engine,
+      components: Arc::default(),
+      instances: Arc::default(),
+    }
+  }
+
+  /// Drops the compiled component and the long-lived instance of a plugin,
+  /// so the next call starts from its current files and settings.
+  pub fn invalidate(&self, plugin_name: &str) {
+    if let Ok(mut components) = self.components.lock() {
+      components.remove(plugin_name);
+    }
+    if let Ok(mut instances) = self.instances.lock() {
+      instances.remove(plugin_name);
+    }
+  }
+
+  /// Calls `function` of a plugin, within its `PluginLimits` and `Sandbox`.
+  /// The function is looked up in the exported `interface`, or by default in
+  /// the plugin's own interface.
+  ///
+  /// A plugin whose manifest sets `persistent` keeps one instance, and so its
+  /// state, across calls. The calls to such a plugin run one at a time. Its
+  /// instance is dropped when a call fails, since a trap can leave it in any
+  /// state.
+  pub async fn call<R: Runtime>(
+    &self,
+    app: &AppHandle<R>,
+    plugin_name: String,
+    interface: Option<&str>,
+    function: String,
+    args: Vec<Value>,
+  ) -> Result<Value, SparusError> {
+    check_plugin_name(&plugin_name)?;
+    if disabled_plugins(app)?.contains(&plugin_name) {
+      return Err(SparusError::PluginDisabled(plugin_name));
+    }
+    quarantine::check(app, &plugin_name)?;
+
+    let result = self
+      .call_instance(app, plugin_name.clone(), interface, function, args)
+      .await;
+    // Failing to keep count must not hide the result of the call.
+    let _ = match &result {
+      Ok(_) => quarantine::record_success(app, &plugin_name, quarantine::Failure::Call),
+      Err(err) if quarantine::is_plugin_failure(err) => {
+        quarantine::record_failure(app, &plugin_name, quarantine::Failure::Call, err)
+      }
+      Err(_) => Ok(()),
+    };
+    result
   }
 
-  pub async fn call(
+  async fn call_instance<R: Runtime>(
     &self,
-    plugin_dir: PathBuf,
+    app: &AppHandle<R>,
     plugin_name: String,
+    interface: Option<&str>,
     function: String,
-    args: Vec<Val>,
+    args: Vec<Value>,
   ) -> Result<Value, SparusError> {
+    let app_data_dir = app.path().app_data_dir()?;
+    let plugin_dir = app_data_dir.join("plugins").join(&plugin_name);
+    let plugin_absolute_path = plugin_dir.join(&plugin_name).with_extension("wasm");
+    let wasm = fs::read(&plugin_absolute_path).await?;
+    let hash = hex::encode(Sha256::digest(&wasm));
+    let persistent = read_manifest(&plugin_dir)
+      .await?
+      .is_some_and(|manifest| manifest.persistent);
+
+    if !persistent {
+      let mut instance = self
+        .instantiate(app, &app_data_dir, &plugin_name, &hash, &wasm)
+        .await?;
+      return instance
+        .call(&plugin_name, interface, &function, &args)
+        .await;
+    }
+
+    let existing = self
+      .instances
+      .lock()
+      .ok()
+      .and_then(|instances| instances.get(&plugin_name).cloned());
+    let instance = match existing {
+      Some(instance) if instance.lock().await.hash == hash => instance,
+      _ => {
+        let instance = Arc::new(Mutex::new(
+          self
+            .instantiate(app, &app_data_dir, &plugin_name, &hash, &wasm)
+            .await?,
+        ));
+        if let Ok(mut instances) = self.instances.lock() {
+          instances.insert(plugin_name.clone(), instance.clone());
+        }
+        instance
+      }
+    };
+
+    let result = instance
+      .lock()
+      .await
+      .call(&plugin_name, interface, &function, &args)
+      .await;
+    if result.is_err() {
+      if let Ok(mut instances) = self.instances.lock() {
+        if instances
+          .get(&plugin_name)
+          .is_some_and(|current| Arc::ptr_eq(current, &instance))
+        {
+          instances.remove(&plugin_name);
+        }
+      }
+    }
+    result
+  }
+
+  async fn instantiate<R: Runtime>(
+    &self,
+    app: &AppHandle<R>,
+    app_data_dir: &Path,
+    plugin_name: &str,
+    hash: &str,
+    wasm: &[u8],
+  ) -> Result<PluginInstance, SparusError> {
+    let sandbox = Sandbox::for_plugin(app, plugin_name).await?;
+    let limits = PluginLimits::for_plugin(app, plugin_name)?;
+    let component = self.component(app_data_dir, plugin_name, hash, wasm)?;
+
     let mut linker = Linker::new(&self.engine);
     wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
-    let wasi = WasiCtx::builder().inherit_stdio().inherit_stderr().build();
+    // Linked even without an approved host, so that a plugin importing
+    // wasi:http still loads and has its requests denied.
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+    host::add_to_linker::<_, HasSelf<_>>(&mut linker, |state| state)?;
+
+    let mut wasi = WasiCtx::builder();
+    wasi.inherit_stdio().inherit_stderr();
+    for (host_path, guest_path, dir_perms, file_perms) in sandbox.preopens {
+      wasi.preopened_dir(host_path, guest_path, dir_perms, file_perms)?;
+    }
+    for (name, value) in sandbox.env {
+      wasi.env(name, value);
+    }
     let state = ComponentRunStates {
-      wasi_ctx: wasi,
+      wasi_ctx: wasi.build(),
       resource_table: ResourceTable::new(),
+      http_ctx: WasiHttpCtx::new(),
+      http_hosts: sandbox.http,
+      limiter: PluginLimiter {
+        limits: StoreLimitsBuilder::new()
+          .memory_size(limits.memory_bytes())
+          .trap_on_grow_failure(true)
+          .build(),
+        exceeded: false,
+      },
+      launcher: Arc::new(app.clone()),
+      host_access: sandbox.host,
+      plugin_name: plugin_name.to_string(),
     };
 
     let mut store = Store::new(&self.engine, state);
+    store.limiter(|state| &mut state.limiter);
+    // Start functions run during instantiation, under the same deadline.
+    store.set_epoch_deadline(epoch_ticks(limits.timeout()));
+    store.epoch_deadline_trap();
+
+    let instance_name = default_interface(&self.engine, &component);
 
-    let plugin_absolute_path = plugin_dir
-      .join(&plugin_name)
+    let instantiated = timeout(
+      limits.timeout(),
+      linker.instantiate_async(&mut store, &component),
+    )
+    .await
+    .map(|instance| instance.map_err(SparusError::from));
+    let instance = check_limits(
+      instantiated,
+      &store,
+      &limits,
+      format!("Plugin {plugin_name}: instantiation"),
+    )?;
+    let interface =
+      instance_name.and_then(|name| instance.get_export_index(&mut store, None, &name));
+
+    Ok(PluginInstance {
+      hash: hash.to_string(),
+      store,
+      instance,
+      interface,
+      limits,
+    })
+  }
+
+  /// The interfaces a plugin exports, with the signatures of their
+  /// functions. This only compiles the plugin, without running it.
+  pub async fn describe<R: Runtime>(
+    &self,
+    app: &AppHandle<R>,
+    plugin_name: &str,
+  ) -> Result<Vec<InterfaceDescription>, SparusError> {
+    check_plugin_name(plugin_name)?;
+    let app_data_dir = app.path().app_data_dir()?;
+    let plugin_absolute_path = app_data_dir
+      .join("plugins")
+      .join(plugin_name)
       .join(plugin_name)
       .with_extension("wasm");
-    let component = Component::from_file(&self.engine, plugin_absolute_path)?;
+    let wasm = fs::read(&plugin_absolute_path).await?;
+    let hash = hex::encode(Sha256::digest(&wasm));
+    let component = self.component(&app_data_dir, plugin_name, &hash, &wasm)?;
+    let default = default_interface(&self.engine, &component);
 
     let component_type = component.component_type();
-    let exports_iter = component_type.exports(&self.engine);
-    let mut instance_name = "";
-    for (name, export_type) in exports_iter {
-      if let ComponentItem::ComponentInstance(_) = export_type.ty {
-        instance_name = name;
-      }
+    let mut interfaces = Vec::new();
+    for (name, export_type) in component_type.exports(&self.engine) {
+      let ComponentItem::ComponentInstance(instance) = export_type.ty else {
+        continue;
+      };
+      let functions = instance
+        .exports(&self.engine)
+        .filter_map(|(function, export_type)| match export_type.ty {
+          ComponentItem::ComponentFunc(func) => Some(FunctionDescription {
+            name: function.to_string(),
+            params: func
+              .params()
+              .map(|(name, ty)| ParamDescription {
+                name: name.to_string(),
+                ty: describe_type(&ty),
+              })
+              .collect(),
+            results: func.results().map(|ty| describe_type(&ty)).collect(),
+            is_async: func.async_(),
+          }),
+          _ => None,
+        })
+        .collect();
+      interfaces.push(InterfaceDescription {
+        name: name.to_string(),
+        default: default.as_deref() == Some(name),
+        functions,
+      });
     }
+    Ok(interfaces)
+  }
 
-    let instance = linker.instantiate_async(&mut store, &component).await?;
-    let instance_index = instance
-      .get_export_index(&mut store, None, instance_name)
-      .ok_or(SparusError::PluginInternal(
-        "instance index not found".to_string(),
-      ))?;
-
-    let func_index = instance
-      .get_export_index(&mut store, Some(&instance_index), &function)
-      .ok_or(SparusError::PluginInternal(
-        "function index not found".to_string(),
-      ))?;
-
-    let func = instance
-      .get_func(&mut store, func_index)
-      .ok_or(SparusError::PluginInternal(
-        "function not found".to_string(),
-      ))?;
+  /// The compiled component of a plugin, from memory, or else from the disk
+  /// cache, or else compiled and added to both.
+  ///
+  /// Both caches are keyed by the hash of the `.wasm`, so an updated plugin is
+  /// never run from a stale compilation.
+  fn component(
+    &self,
+    app_data_dir: &Path,
+    plugin_name: &str,
+    hash: &str,
+    wasm: &[u8],
+  ) -> Result<Component, SparusError> {
+    if let Ok(components) = self.components.lock() {
+      if let Some((cached_hash, component)) = components.get(plugin_name) {
+        if cached_hash == hash {
+          return Ok(component.clone());
+        }
+      }
+    }
 
-    let func_ty = func.ty(&store);
-    let result_types = func_ty.results();
+    let cache_dir = app_data_dir.join("plugin_cache").join(plugin_name);
+    let cache_file = cache_dir.join(hash).with_extension("cwasm");
+    // SAFETY: the launcher wrote this file itself with `Component::serialize`,
+    // for this hash. A file from another wasmtime version or configuration is
+    // rejected by `deserialize_file`, and then compiled again.
+    let cached = match cache_file.is_file() {
+      true => unsafe { Component::deserialize_file(&self.engine, &cache_file) }.ok(),
+      false => None,
+    };
+    let component = match cached {
+      Some(component) => component,
+      None => {
+        let component = Component::new(&self.engine, wasm)?;
+        // Only the compilation of the installed version is worth keeping.
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        if std::fs::create_dir_all(&cache_dir).is_ok() {
+          if let Ok(serialized) = component.serialize() {
+            let _ = std::fs::write(&cache_file, serialized);
+          }
+        }
+        component
+      }
+    };
 
-    let mut results: Vec<Val> = result_types.map(default_val_from_type).collect();
-    if func_ty.async_() {
-      store
-        .run_concurrent(async |accessor| -> Result<(), SparusError> {
-          func.call_concurrent(accessor, &args, &mut results).await?;
-          Ok(())
-        })
-        .await??
-    } else {
-      func.call_async(&mut store, &args, &mut results).await?
+    if let Ok(mut components) = self.components.lock() {
+      components.insert(
+        plugin_name.to_string(),
+        (hash.to_string(), component.clone()),
+      );
     }
-
-    Ok(results_to_json(results))
+    Ok(component)
   }
 }
 
+/// Calls `function` of a plugin, in the exported `interface` when one is
+/// named, or else in its default interface. The function must be listed in the
+/// `exports` of the plugin's manifest, so a plugin without one can't be called
+/// from the frontend, and the hooks are left to the launcher.
 #[command]
 pub async fn call_wasm_plugin_function<R: Runtime>(
   handle: AppHandle<R>,
   state: State<'_, PluginSystem>,
   plugin: String,
+  interface: Option<String>,
   function: String,
   args: Option<Value>,
 ) -> Result<Value, SparusError> {
   let plugins_args = match args {
-    Some(existing_arg) => std_array_to_vals(&existing_arg).ok_or(SparusError::Wasmtime(
-      wasmtime::Error::msg("Epected an array"),
-    )),
-    None => Ok(Vec::new()),
-  }?;
-  let app_data_dir = handle.path().app_data_dir()?;
-  let plugins_dir = app_data_dir.join("plugins");
+    Some(Value::Array(existing_args)) => existing_args,
+    Some(Value::Null) | None => Vec::new(),
+    Some(_) => {
+      return Err(SparusError::PluginValue(
+        "Expected an array of arguments".to_string(),
+      ))
+    }
+  };
+  check_plugin_name(&plugin)?;
+  if interface
+    .as_deref()
+    .is_some_and(|interface| interface.split('@').next() == Some(HOOKS_INTERFACE))
+  {
+    return Err(SparusError::PluginInternal(format!(
+      "The hooks of plugin {plugin} are only called by the launcher"
+    )));
+  }
+  let plugin_dir = handle.path().app_data_dir()?.join("plugins").join(&plugin);
+  let exported = read_manifest(&plugin_dir)
+    .await?
+    .is_some_and(|manifest| manifest.exports.contains(&function));
+  if !exported {
+    return Err(SparusError::PluginInternal(format!(
+      "Plugin {plugin} doesn't export {function} to the frontend"
+    )));
+  }
   state
-    .call(plugins_dir, plugin, function, plugins_args)
+    .call(
+      &handle,
+      plugin,
+      interface.as_deref(),
+      function,
+      plugins_args,
+    )
     .await
 }
 
+/// Lists the interfaces and functions a plugin exports, with their parameter
+/// and result types.
+#[command]
+pub async fn describe_wasm_plugin<R: Runtime>(
+  handle: AppHandle<R>,
+  state: State<'_, PluginSystem>,
+  plugin: String,
+) -> Result<Vec<InterfaceDescription>, SparusError> {
+  state.describe(&handle, &plugin).await
+}
+
+/// Directories of the plugins that have a `frontend.js` to load. Plugins that
+/// are disabled, quarantined or need a newer launcher are left out.
 #[command]
 pub async fn js_plugins_path<R: Runtime>(app: AppHandle<R>) -> Result<Vec<String>, SparusError> {
+  let launcher_version = &app.package_info().version;
   let plugins_dir = app.path().app_data_dir()?.join("plugins");
+  let mut skipped = disabled_plugins(&app)?;
+  skipped.extend(quarantine::quarantined_plugins(&app)?);
 
   if !plugins_dir.exists() {
     return Ok(Vec::new());
@@ -142,7 +1213,15 @@ pub async fn js_plugins_path<R: Runtime>(app: AppHandle<R>) -> Result<Vec<String
 
   while let Some(entry) = entries.next_entry().await? {
     let path = entry.path();
+    if skipped.contains(&entry.file_name().to_string_lossy().to_string()) {
+      continue;
+    }
     if path.join("frontend.js").is_file() {
+      if let Some(manifest) = read_manifest(&path).await? {
+        if !manifest.is_compatible(launcher_version) {
+          continue;
+        }
+      }
       let plugin_dir = path.strip_prefix(&plugins_dir)?;
       let plugin_dir_string = plugin_dir.display().to_string();
       plugins.push(plugin_dir_string);
@@ -151,95 +1230,523 @@ pub async fn js_plugins_path<R: Runtime>(app: AppHandle<R>) -> Result<Vec<String
   Ok(plugins)
 }
 
-fn std_val_to_wasm_val(value: &Value) -> Option<Val> {
-  match value {
-    Value::Null => Some(Val::Option(None)),
-    Value::Bool(b) => Some(Val::Bool(*b)),
-    Value::Number(n) => {
-      if let Some(i) = n.as_i64() {
-        if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
-          Some(Val::S32(i as i32))
-        } else {
-          Some(Val::S64(i))
+/// Converts the JSON `value` to a component value of type `ty`. `path` names
+/// the value in errors, like `settings.servers[2]`.
+///
+/// The JSON shape of each WIT type follows the JavaScript bindings of jco:
+/// records are objects keyed by field name, tuples and lists are arrays, enums
+/// are strings, flags are arrays of strings, options are `null` or the value,
+/// variants are `{"tag": case, "val": payload}` and results are `{"ok": value}`
+/// or `{"err": value}`.
+fn json_to_val(value: &Value, ty: &Type, path: &str) -> Result<Val, SparusError> {
+  let mismatch =
+    |expected: &str| SparusError::PluginValue(format!("{path}: expected {expected}, got {value}"));
+
+  match ty {
+    Type::Bool => value
+      .as_bool()
+      .map(Val::Bool)
+      .ok_or_else(|| mismatch("a bool")),
+    Type::S8 => integer(value).map(Val::S8).ok_or_else(|| mismatch("an s8")),
+    Type::U8 => integer(value).map(Val::U8).ok_or_else(|| mismatch("a u8")),
+    Type::S16 => integer(value)
+      .map(Val::S16)
+      .ok_or_else(|| mismatch("an s16")),
+    Type::U16 => integer(value)
+      .map(Val::U16)
+      .ok_or_else(|| mismatch("a u16")),
+    Type::S32 => integer(value)
+      .map(Val::S32)
+      .ok_or_else(|| mismatch("an s32")),
+    Type::U32 => integer(value)
+      .map(Val::U32)
+      .ok_or_else(|| mismatch("a u32")),
+    Type::S64 => integer(value)
+      .map(Val::S64)
+      .ok_or_else(|| mismatch("an s64")),
+    Type::U64 => integer(value)
+      .map(Val::U64)
+      .ok_or_else(|| mismatch("a u64")),
+    Type::Float32 => value
+      .as_f64()
+      .map(|float| Val::Float32(float as f32))
+      .ok_or_else(|| mismatch("an f32")),
+    Type::Float64 => value
+      .as_f64()
+      .map(Val::Float64)
+      .ok_or_else(|| mismatch("an f64")),
+    Type::Char => {
+      let mut chars = value.as_str().unwrap_or_default().chars();
+      match (chars.next(), chars.next()) {
+        (Some(char), None) => Ok(Val::Char(char)),
+        _ => Err(mismatch("a single character string")),
+      }
+    }
+    Type::String => value
+      .as_str()
+      .map(|string| Val::String(string.to_string()))
+      .ok_or_else(|| mismatch("a string")),
+    Type::List(list) => {
+      let items = value.as_array().ok_or_else(|| mismatch("an array"))?;
+      let item_ty = list.ty();
+      items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| json_to_val(item, &item_ty, &format!("{path}[{index}]")))
+        .collect::<Result<_, _>>()
+        .map(Val::List)
+    }
+    Type::Record(record) => {
+      let object = value.as_object().ok_or_else(|| mismatch("an object"))?;
+      if let Some(unknown) = object
+        .keys()
+        .find(|key| !record.fields().any(|field| field.name == key.as_str()))
+      {
+        return Err(SparusError::PluginValue(format!(
+          "{path}: unknown field {unknown}"
+        )));
+      }
+      record
+        .fields()
+        .map(|field| {
+          let field_path = format!("{path}.{}", field.name);
+          let field_value = match (object.get(field.name), &field.ty) {
+            (Some(field_value), ty) => json_to_val(field_value, ty, &field_path)?,
+            (None, Type::Option(_)) => Val::Option(None),
+            (None, _) => {
+              return Err(SparusError::PluginValue(format!(
+                "{field_path}: missing field"
+              )))
+            }
+          };
+          Ok((field.name.to_string(), field_value))
+        })
+        .collect::<Result<_, _>>()
+        .map(Val::Record)
+    }
+    Type::Tuple(tuple) => {
+      let items = value.as_array().ok_or_else(|| mismatch("an array"))?;
+      if items.len() != tuple.types().len() {
+        return Err(mismatch(&format!(
+          "an array of {} items",
+          tuple.types().len()
+        )));
+      }
+      tuple
+        .types()
+        .zip(items)
+        .enumerate()
+        .map(|(index, (item_ty, item))| json_to_val(item, &item_ty, &format!("{path}[{index}]")))
+        .collect::<Result<_, _>>()
+        .map(Val::Tuple)
+    }
+    Type::Variant(variant) => {
+      // A case without payload can also be given as its bare name.
+      let (tag, payload) = match value {
+        Value::String(tag) => (tag.as_str(), None),
+        Value::Object(object) => (
+          object
+            .get("tag")
+            .and_then(Value::as_str)
+            .ok_or_else(|| mismatch("a {\"tag\", \"val\"} object"))?,
+          object.get("val").filter(|payload| !payload.is_null()),
+        ),
+        _ => return Err(mismatch("a {\"tag\", \"val\"} object")),
+      };
+      let case = variant
+        .cases()
+        .find(|case| case.name == tag)
+        .ok_or_else(|| SparusError::PluginValue(format!("{path}: unknown case {tag}")))?;
+      let payload = match (case.ty, payload) {
+        (Some(payload_ty), Some(payload)) => Some(Box::new(json_to_val(
+          payload,
+          &payload_ty,
+          &format!("{path}.val"),
+        )?)),
+        (Some(Type::Option(_)), None) => Some(Box::new(Val::Option(None))),
+        (Some(_), None) => {
+          return Err(SparusError::PluginValue(format!(
+            "{path}: case {tag} needs a val"
+          )))
         }
-      } else if let Some(f) = n.as_f64() {
-        if f >= f32::MIN as f64 && f <= f32::MAX as f64 {
-          Some(Val::Float32(f as f32))
-        } else {
-          Some(Val::Float64(f))
+        (None, Some(_)) => {
+          return Err(SparusError::PluginValue(format!(
+            "{path}: case {tag} has no val"
+          )))
         }
+        (None, None) => None,
+      };
+      Ok(Val::Variant(tag.to_string(), payload))
+    }
+    Type::Enum(enum_ty) => match value.as_str() {
+      Some(name) if enum_ty.names().any(|case| case == name) => Ok(Val::Enum(name.to_string())),
+      _ => Err(mismatch(&format!(
+        "one of {}",
+        enum_ty.names().collect::<Vec<_>>().join(", ")
+      ))),
+    },
+    Type::Option(option) => match value {
+      Value::Null => Ok(Val::Option(None)),
+      value => Ok(Val::Option(Some(Box::new(json_to_val(
+        value,
+        &option.ty(),
+        path,
+      )?)))),
+    },
+    Type::Result(result) => {
+      let object = value
+        .as_object()
+        .filter(|object| object.len() == 1)
+        .ok_or_else(|| mismatch("an {\"ok\"} or {\"err\"} object"))?;
+      let payload = |payload_ty: Option<Type>, key: &str| match payload_ty {
+        Some(payload_ty) => json_to_val(&object[key], &payload_ty, &format!("{path}.{key}"))
+          .map(|payload| Some(Box::new(payload))),
+        None => Ok(None),
+      };
+      if object.contains_key("ok") {
+        Ok(Val::Result(Ok(payload(result.ok(), "ok")?)))
+      } else if object.contains_key("err") {
+        Ok(Val::Result(Err(payload(result.err(), "err")?)))
       } else {
-        None
+        Err(mismatch("an {\"ok\"} or {\"err\"} object"))
       }
     }
-    Value::String(s) => Some(Val::String(s.clone())),
-    Value::Array(arr) => {
-      let vals: Vec<Val> = arr.iter().filter_map(std_val_to_wasm_val).collect();
-      Some(Val::List(vals))
-    }
-    Value::Object(map) => {
-      let fields: Vec<(String, Val)> = map
+    Type::Flags(flags) => {
+      let names = value
+        .as_array()
+        .ok_or_else(|| mismatch("an array of flags"))?;
+      names
         .iter()
-        .filter_map(|(k, v)| std_val_to_wasm_val(v).map(|vv| (k.clone(), vv)))
-        .collect();
-      Some(Val::Record(fields))
+        .map(|name| match name.as_str() {
+          Some(name) if flags.names().any(|flag| flag == name) => Ok(name.to_string()),
+          _ => Err(SparusError::PluginValue(format!(
+            "{path}: unknown flag {name}"
+          ))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Val::Flags)
     }
+    _ => Err(SparusError::PluginValue(format!(
+      "{path}: resources, futures and streams can't be passed from JSON"
+    ))),
+  }
+}
+
+/// A JSON number as an integer of type `T`, if it is one and fits.
+fn integer<T: TryFrom<i64> + TryFrom<u64>>(value: &Value) -> Option<T> {
+  let number = value.as_number()?;
+  match number.as_i64() {
+    Some(signed) => T::try_from(signed).ok(),
+    None => T::try_from(number.as_u64()?).ok(),
+  }
+}
+
+/// Describes a WIT type as JSON, for `describe_wasm_plugin`. Primitive types
+/// are their WIT name, like `"u32"`, and the others an object keyed by their
+/// kind, like `{"list": "string"}` or `{"option": "u8"}`.
+fn describe_type(ty: &Type) -> Value {
+  match ty {
+    Type::Bool => json!("bool"),
+    Type::S8 => json!("s8"),
+    Type::U8 => json!("u8"),
+    Type::S16 => json!("s16"),
+    Type::U16 => json!("u16"),
+    Type::S32 => json!("s32"),
+    Type::U32 => json!("u32"),
+    Type::S64 => json!("s64"),
+    Type::U64 => json!("u64"),
+    Type::Float32 => json!("f32"),
+    Type::Float64 => json!("f64"),
+    Type::Char => json!("char"),
+    Type::String => json!("string"),
+    Type::List(list) => json!({ "list": describe_type(&list.ty()) }),
+    Type::Record(record) => json!({
+      "record": record
+        .fields()
+        .map(|field| json!({ "name": field.name, "type": describe_type(&field.ty) }))
+        .collect::<Vec<_>>()
+    }),
+    Type::Tuple(tuple) => json!({
+      "tuple": tuple.types().map(|ty| describe_type(&ty)).collect::<Vec<_>>()
+    }),
+    Type::Variant(variant) => json!({
+      "variant": variant
+        .cases()
+        .map(|case| json!({ "name": case.name, "type": case.ty.as_ref().map(describe_type) }))
+        .collect::<Vec<_>>()
+    }),
+    Type::Enum(enum_ty) => json!({ "enum": enum_ty.names().collect::<Vec<_>>() }),
+    Type::Option(option) => json!({ "option": describe_type(&option.ty()) }),
+    Type::Result(result) => json!({
+      "result": {
+        "ok": result.ok().as_ref().map(describe_type),
+        "err": result.err().as_ref().map(describe_type),
+      }
+    }),
+    Type::Flags(flags) => json!({ "flags": flags.names().collect::<Vec<_>>() }),
+    Type::Own(_) => json!({ "own": "resource" }),
+    Type::Borrow(_) => json!({ "borrow": "resource" }),
+    Type::Future(future) => json!({ "future": future.ty().as_ref().map(describe_type) }),
+    Type::Stream(stream) => json!({ "stream": stream.ty().as_ref().map(describe_type) }),
+    Type::ErrorContext => json!("error-context"),
   }
 }
 
-fn val_to_json(val: Val) -> Value {
-  match val {
+/// Converts a component value to JSON, in the shapes `json_to_val` reads.
+fn val_to_json(val: Val) -> Result<Value, SparusError> {
+  Ok(match val {
     Val::Bool(b) => Value::Bool(b),
-    Val::S32(n) => Value::Number(n.into()),
-    Val::S64(n) => Value::Number(n.into()),
-    Val::U32(n) => Value::Number(n.into()),
-    Val::U64(n) => Value::Number(n.into()),
+    Val::S8(n) => n.into(),
+    Val::U8(n) => n.into(),
+    Val::S16(n) => n.into(),
+    Val::U16(n) => n.into(),
+    Val::S32(n) => n.into(),
+    Val::U32(n) => n.into(),
+    Val::S64(n) => n.into(),
+    Val::U64(n) => n.into(),
+    // NaN and infinities have no JSON representation.
     Val::Float32(f) => serde_json::Number::from_f64(f as f64)
       .map(Value::Number)
       .unwrap_or(Value::Null),
     Val::Float64(f) => serde_json::Number::from_f64(f)
       .map(Value::Number)
       .unwrap_or(Value::Null),
+    Val::Char(c) => Value::String(c.to_string()),
     Val::String(s) => Value::String(s),
-    _ => Value::Null,
-  }
+    Val::List(items) | Val::Tuple(items) => Value::Array(
+      items
+        .into_iter()
+        .map(val_to_json)
+        .collect::<Result<_, _>>()?,
+    ),
+    Val::Record(fields) => Value::Object(
+      fields
+        .into_iter()
+        .map(|(name, field)| Ok((name, val_to_json(field)?)))
+        .collect::<Result<_, SparusError>>()?,
+    ),
+    Val::Variant(tag, payload) => {
+      let mut variant = serde_json::Map::new();
+      variant.insert("tag".to_string(), Value::String(tag));
+      if let Some(payload) = payload {
+        variant.insert("val".to_string(), val_to_json(*payload)?);
+      }
+      Value::Object(variant)
+    }
+    Val::Enum(name) => Value::String(name),
+    Val::Option(None) => Value::Null,
+    Val::Option(Some(payload)) => val_to_json(*payload)?,
+    Val::Result(result) => {
+      let (key, payload) = match result {
+        Ok(payload) => ("ok", payload),
+        Err(payload) => ("err", payload),
+      };
+      let payload = match payload {
+        Some(payload) => val_to_json(*payload)?,
+        None => Value::Null,
+      };
+      serde_json::json!({ key: payload })
+    }
+    Val::Flags(names) => Value::Array(names.into_iter().map(Value::String).collect()),
+    _ => {
+      return Err(SparusError::PluginValue(
+        "resources, futures and streams can't be returned as JSON".to_string(),
+      ))
+    }
+  })
 }
 
-fn results_to_json(results: Vec<Val>) -> Value {
+fn results_to_json(results: Vec<Val>) -> Result<Value, SparusError> {
   match results.len() {
-    0 => Value::Null,
+    0 => Ok(Value::Null),
     1 => val_to_json(results.into_iter().next().unwrap()),
-    _ => Value::Array(results.into_iter().map(val_to_json).collect()),
+    _ => Ok(Value::Array(
+      results
+        .into_iter()
+        .map(val_to_json)
+        .collect::<Result<_, _>>()?,
+    )),
   }
 }
 
-fn default_val_from_type(ty: Type) -> Val {
-  match ty {
-    Type::Bool => Val::Bool(false),
-    Type::S8 => Val::S8(0),
-    Type::S16 => Val::S16(0),
-    Type::S32 => Val::S32(0),
-    Type::S64 => Val::S64(0),
-    Type::U8 => Val::U8(0),
-    Type::U16 => Val::U16(0),
-    Type::U32 => Val::U32(0),
-    Type::U64 => Val::U64(0),
-    Type::Float32 => Val::Float32(0.0),
-    Type::Float64 => Val::Float64(0.0),
-    Type::String => Val::String(String::new()),
-    Type::Result(result_ty) => {
-      if let Some(ok_ty) = result_ty.ok() {
-        Val::Result(Ok(Some(Box::new(default_val_from_type(ok_ty)))))
-      } else {
-        Val::Result(Ok(None))
-      }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::executor::block_on;
+
+  fn env_capabilities() -> PluginCapabilities {
+    PluginCapabilities {
+      env: vec!["LANG".to_string()],
+      ..Default::default()
     }
-    _ => unimplemented!("Type non supporté dynamiquement"),
   }
-}
 
-fn std_array_to_vals(value: &Value) -> Option<Vec<Val>> {
-  value
-    .as_array()
-    .map(|arr| arr.iter().filter_map(std_val_to_wasm_val).collect())
+  #[test]
+  fn declined_capabilities_are_not_approved() {
+    let current = PluginPermissions {
+      requested: env_capabilities(),
+      approved: PluginCapabilities::default(),
+    };
+    let result = block_on(approve_capabilities(
+      "my-plugin",
+      &current,
+      &env_capabilities(),
+      |_| async { Ok(false) },
+    ));
+    assert!(
+      matches!(result, Err(SparusError::PermissionsDeclined(plugin)) if plugin == "my-plugin")
+    );
+  }
+
+  #[test]
+  fn withdrawing_capabilities_needs_no_confirmation() {
+    let current = PluginPermissions {
+      requested: env_capabilities(),
+      approved: env_capabilities(),
+    };
+    let approved = block_on(approve_capabilities(
+      "my-plugin",
+      &current,
+      &PluginCapabilities::default(),
+      |_| async { panic!("nothing new was granted") },
+    ))
+    .unwrap();
+    assert!(approved.descriptions().is_empty());
+  }
+
+  /// Parameter types of a function `f` taking the types these tests convert.
+  /// It is lifted from a core function that is never called.
+  fn param_types() -> HashMap<String, Type> {
+    let engine = Engine::default();
+    let component = Component::new(
+      &engine,
+      r#"
+        (component
+          (core module $m
+            (memory (export "memory") 1)
+            (func (export "realloc") (param i32 i32 i32 i32) (result i32) unreachable)
+            (func (export "f")
+              (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)
+              unreachable))
+          (core instance $i (instantiate $m))
+          (type $profile (record (field "name" string) (field "nick" (option string))))
+          (export $profile' "profile" (type $profile))
+          (type $state (variant (case "idle") (case "downloading" u32)))
+          (export $state' "state" (type $state))
+          (func (export "f")
+            (param "small" u8)
+            (param "profile" $profile')
+            (param "state" $state')
+            (param "outcome" (result u32 (error string)))
+            (param "names" (list string))
+            (canon lift (core func $i "f") (memory $i "memory") (realloc (func $i "realloc"))))
+        )
+      "#,
+    )
+    .unwrap();
+    let component_type = component.component_type();
+    let (_, export_type) = component_type
+      .exports(&engine)
+      .find(|(name, _)| *name == "f")
+      .unwrap();
+    let ComponentItem::ComponentFunc(func) = export_type.ty else {
+      panic!("f is not a function");
+    };
+    func
+      .params()
+      .map(|(name, ty)| (name.to_string(), ty))
+      .collect()
+  }
+
+  #[test]
+  fn integer_out_of_range() {
+    assert_eq!(integer::<u8>(&json!(255)), Some(255));
+    assert_eq!(integer::<u8>(&json!(256)), None);
+    assert_eq!(integer::<u8>(&json!(-1)), None);
+    assert_eq!(integer::<i64>(&json!(u64::MAX)), None);
+    assert_eq!(integer::<u64>(&json!(u64::MAX)), Some(u64::MAX));
+    assert_eq!(integer::<u32>(&json!(1.5)), None);
+
+    let types = param_types();
+    assert!(matches!(
+      json_to_val(&json!(300), &types["small"], "small"),
+      Err(SparusError::PluginValue(_))
+    ));
+  }
+
+  #[test]
+  fn missing_option_field() {
+    let types = param_types();
+    assert_eq!(
+      json_to_val(&json!({ "name": "ada" }), &types["profile"], "profile").unwrap(),
+      Val::Record(vec![
+        ("name".to_string(), Val::String("ada".to_string())),
+        ("nick".to_string(), Val::Option(None)),
+      ])
+    );
+    assert!(json_to_val(&json!({ "nick": "a" }), &types["profile"], "profile").is_err());
+    assert!(json_to_val(
+      &json!({ "name": "ada", "age": 36 }),
+      &types["profile"],
+      "profile"
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn bare_tag_variant() {
+    let types = param_types();
+    assert_eq!(
+      json_to_val(&json!("idle"), &types["state"], "state").unwrap(),
+      Val::Variant("idle".to_string(), None)
+    );
+    assert_eq!(
+      json_to_val(
+        &json!({ "tag": "downloading", "val": 40 }),
+        &types["state"],
+        "state"
+      )
+      .unwrap(),
+      Val::Variant("downloading".to_string(), Some(Box::new(Val::U32(40))))
+    );
+    // A case with a payload can't be given bare.
+    assert!(json_to_val(&json!("downloading"), &types["state"], "state").is_err());
+    assert!(json_to_val(&json!("paused"), &types["state"], "state").is_err());
+  }
+
+  #[test]
+  fn result_shape() {
+    let types = param_types();
+    let ok = json_to_val(&json!({ "ok": 3 }), &types["outcome"], "outcome").unwrap();
+    assert_eq!(ok, Val::Result(Ok(Some(Box::new(Val::U32(3))))));
+    assert_eq!(val_to_json(ok).unwrap(), json!({ "ok": 3 }));
+
+    let err = json_to_val(&json!({ "err": "offline" }), &types["outcome"], "outcome").unwrap();
+    assert_eq!(
+      err,
+      Val::Result(Err(Some(Box::new(Val::String("offline".to_string())))))
+    );
+    assert_eq!(val_to_json(err).unwrap(), json!({ "err": "offline" }));
+
+    for invalid in [json!({ "ok": 3, "err": "offline" }), json!({}), json!(3)] {
+      assert!(json_to_val(&invalid, &types["outcome"], "outcome").is_err());
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let types = param_types();
+    for (param, value) in [
+      ("small", json!(7)),
+      ("profile", json!({ "name": "ada", "nick": "countess" })),
+      ("profile", json!({ "name": "ada", "nick": null })),
+      ("state", json!({ "tag": "downloading", "val": 40 })),
+      ("state", json!({ "tag": "idle" })),
+      ("outcome", json!({ "err": "offline" })),
+      ("names", json!(["ada", "grace"])),
+    ] {
+      let val = json_to_val(&value, &types[param], param).unwrap();
+      assert_eq!(val_to_json(val).unwrap(), value, "{param}");
+    }
+  }
 }
@@ -0,0 +1,197 @@
+use crate::{
+  errors::SparusError,
+  plugins::{self, PluginSystem},
+  rpc,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::{command, AppHandle, Manager, Runtime};
+use tauri_plugin_store::StoreExt;
+
+/// Store key holding the `PluginHealth` of the plugins that failed, by plugin
+/// name.
+const PLUGIN_HEALTH_KEY: &str = "plugin_health";
+
+/// Consecutive failures after which a plugin is quarantined.
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+/// What failed. Each kind is counted on its own, so that a `frontend.js` that
+/// loads doesn't hide a `.wasm` that keeps trapping, and the other way around.
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+  /// The frontend couldn't load the `frontend.js`.
+  Load,
+  /// A call to the `.wasm` trapped or ran into its limits.
+  Call,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PluginHealth {
+  /// Loads of the `frontend.js` that failed since the last one that
+  /// succeeded.
+  #[serde(default)]
+  load_failures: u32,
+  /// Calls to the `.wasm` that failed since the last one that succeeded.
+  #[serde(default)]
+  call_failures: u32,
+  #[serde(default)]
+  quarantined: bool,
+  /// The failure that quarantined the plugin.
+  #[serde(default)]
+  last_error: Option<String>,
+}
+
+impl PluginHealth {
+  fn failures(&mut self, failure: Failure) -> &mut u32 {
+    match failure {
+      Failure::Load => &mut self.load_failures,
+      Failure::Call => &mut self.call_failures,
+    }
+  }
+}
+
+fn plugin_health<R: Runtime>(
+  app: &AppHandle<R>,
+) -> Result<HashMap<String, PluginHealth>, SparusError> {
+  let store = app.store("Sparus.json")?;
+  match store.get(PLUGIN_HEALTH_KEY) {
+    Some(health_json) => Ok(serde_json::from_value(health_json)?),
+    None => Ok(HashMap::new()),
+  }
+}
+
+fn save_plugin_health<R: Runtime>(
+  app: &AppHandle<R>,
+  health: &HashMap<String, PluginHealth>,
+) -> Result<(), SparusError> {
+  let store = app.store("Sparus.json")?;
+  store.set(PLUGIN_HEALTH_KEY, serde_json::to_value(health)?);
+  store.save()?;
+  Ok(())
+}
+
+/// Plugins that failed too many times in a row. They stay installed, but their
+/// `frontend.js` isn't loaded and their `.wasm` isn't called until the
+/// quarantine is cleared.
+pub fn quarantined_plugins<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<String>, SparusError> {
+  Ok(
+    plugin_health(app)?
+      .into_iter()
+      .filter(|(_, health)| health.quarantined)
+      .map(|(plugin, _)| plugin)
+      .collect(),
+  )
+}
+
+/// Fails with `SparusError::PluginQuarantined` when the plugin is
+/// quarantined.
+pub fn check<R: Runtime>(app: &AppHandle<R>, plugin: &str) -> Result<(), SparusError> {
+  match plugin_health(app)?.remove(plugin) {
+    Some(health) if health.quarantined => Err(SparusError::PluginQuarantined {
+      plugin: plugin.to_string(),
+      last_error: health.last_error.unwrap_or_default(),
+    }),
+    _ => Ok(()),
+  }
+}
+
+/// Whether `err` is the plugin's own failure, a trap or a limit it ran into,
+/// rather than a bad call or a host problem.
+pub fn is_plugin_failure(err: &SparusError) -> bool {
+  matches!(err, SparusError::Wasmtime(_) | SparusError::PluginLimit(_))
+}
+
+/// Resets the count of `failure` of a plugin after a call or load that
+/// succeeded.
+pub fn record_success<R: Runtime>(
+  app: &AppHandle<R>,
+  plugin: &str,
+  failure: Failure,
+) -> Result<(), SparusError> {
+  let mut health = plugin_health(app)?;
+  // Most calls succeed, and the store is only written when there is a count
+  // to reset.
+  let Some(plugin_health) = health.get_mut(plugin) else {
+    return Ok(());
+  };
+  if plugin_health.quarantined || *plugin_health.failures(failure) == 0 {
+    return Ok(());
+  }
+  *plugin_health.failures(failure) = 0;
+  if plugin_health.load_failures == 0 && plugin_health.call_failures == 0 {
+    health.remove(plugin);
+  }
+  save_plugin_health(app, &health)
+}
+
+/// Counts a `failure` of a plugin, and quarantines it after
+/// `MAX_CONSECUTIVE_FAILURES` of the same kind in a row. The quarantine is
+/// reported through `sparus://pluginerror`.
+pub fn record_failure<R: Runtime>(
+  app: &AppHandle<R>,
+  plugin: &str,
+  failure: Failure,
+  err: &SparusError,
+) -> Result<(), SparusError> {
+  let mut health = plugin_health(app)?;
+  let plugin_health = health.entry(plugin.to_string()).or_default();
+  if plugin_health.quarantined {
+    return Ok(());
+  }
+  *plugin_health.failures(failure) += 1;
+  if *plugin_health.failures(failure) < MAX_CONSECUTIVE_FAILURES {
+    return save_plugin_health(app, &health);
+  }
+
+  plugin_health.quarantined = true;
+  plugin_health.last_error = Some(err.to_string());
+  save_plugin_health(app, &health)?;
+  app.state::<PluginSystem>().invalidate(plugin);
+  rpc::report(
+    app,
+    SparusError::PluginQuarantined {
+      plugin: plugin.to_string(),
+      last_error: err.to_string(),
+    },
+  );
+  Ok(())
+}
+
+#[command]
+pub fn get_quarantined_plugins<R: Runtime>(app: AppHandle<R>) -> Result<Vec<String>, SparusError> {
+  quarantined_plugins(&app)
+}
+
+/// Lifts the quarantine of a plugin, and gives it a fresh failure count.
+#[command]
+pub fn clear_plugin_quarantine<R: Runtime>(
+  app: AppHandle<R>,
+  plugin: String,
+) -> Result<(), SparusError> {
+  plugins::check_plugin_name(&plugin)?;
+  let mut health = plugin_health(&app)?;
+  if health.remove(&plugin).is_some() {
+    save_plugin_health(&app, &health)?;
+  }
+  Ok(())
+}
+
+/// Lets the frontend count whether the `frontend.js` of a plugin loaded, since
+/// the launcher doesn't load it itself.
+#[command]
+pub fn report_plugin_load<R: Runtime>(
+  app: AppHandle<R>,
+  plugin: String,
+  error: Option<String>,
+) -> Result<(), SparusError> {
+  plugins::check_plugin_name(&plugin)?;
+  match error {
+    Some(error) => record_failure(
+      &app,
+      &plugin,
+      Failure::Load,
+      &SparusError::PluginInternal(error),
+    ),
+    None => record_success(&app, &plugin, Failure::Load),
+  }
+}
@@ -2,19 +2,32 @@ pub mod sparus {
   tonic::include_proto!("sparus");
 }
 
-use crate::{errors::SparusError, rpc::reqwest::StatusCode};
-use futures::StreamExt;
+use crate::{
+  bandwidth::BandwidthLimiter,
+  errors::SparusError,
+  plugins::{self, PluginManifest, PluginSystem, MANIFEST_FILE},
+  rpc::reqwest::StatusCode,
+  settings,
+};
+use futures::{
+  future::{self, Either},
+  StreamExt,
+};
+use minisign_verify::{PublicKey, Signature};
 use semver::Version;
-use sparus::{event_client::EventClient, EventType, Plugins};
+use sha2::{Digest, Sha256};
+use sparus::{event_client::EventClient, EventType, Message, Plugins};
 use std::{
   collections::HashMap,
   path::{Path, PathBuf},
+  pin::pin,
 };
-use tauri::{AppHandle, Emitter, Runtime};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
 use tauri_plugin_http::reqwest;
 use tokio::{
-  fs::{self, File},
-  io::AsyncWriteExt,
+  fs::{self, File, OpenOptions},
+  io::{AsyncReadExt, AsyncWriteExt},
+  sync::watch,
   time::{sleep, Duration},
 };
 use tonic::transport::Channel;
@@ -27,7 +40,37 @@ use tonic::transport::Channel;
 /// `sparus://downloadinfos`.
 pub const PLUGIN_ERROR_EVENT: &str = "sparus://pluginerror";
 
-fn report<R: Runtime>(app: &AppHandle<R>, err: SparusError) {
+/// Directory of the app data dir where plugins are downloaded and staged before
+/// they replace the installed ones. It sits outside `plugins` so that a partial
+/// download is never mistaken for an installed plugin.
+const PLUGIN_DOWNLOADS_DIR: &str = "plugin_downloads";
+
+/// Key of the bundled `Sparus.json` holding the minisign public keys, base64
+/// encoded, that plugin files must be signed with. The store copy is ignored,
+/// since the webview can write it.
+const PLUGIN_KEYS_KEY: &str = "plugin_public_keys";
+
+/// Plugin public keys built into the launcher, separated by commas, trusted on
+/// top of the bundled ones.
+const BUILT_IN_PLUGIN_KEYS: Option<&str> = option_env!("SPARUS_PLUGIN_PUBLIC_KEYS");
+
+/// Makes the CMS subscription start over, so that the CMS learns about a
+/// change of the plugins it was subscribed with.
+pub struct PluginSubscription(watch::Sender<()>);
+
+impl Default for PluginSubscription {
+  fn default() -> Self {
+    Self(watch::channel(()).0)
+  }
+}
+
+impl PluginSubscription {
+  pub fn resubscribe(&self) {
+    self.0.send_replace(());
+  }
+}
+
+pub fn report<R: Runtime>(app: &AppHandle<R>, err: SparusError) {
   // `Emitter::emit` needs `Serialize + Clone`, and `SparusError` can't be
   // `Clone` (it wraps `io::Error` and friends). Going through `to_value` reuses
   // the existing `Serialize` impl, so the `kind` stays consistent with every
@@ -56,11 +99,16 @@ async fn start_streaming<R: Runtime>(
   launcher_name: String,
 ) -> Result<(), SparusError> {
   let app_data_dir_string = app_data_dir.display().to_string();
+  // Subscribed before reading the disabled plugins, so that no change is
+  // missed.
+  let mut changes = app.state::<PluginSubscription>().0.subscribe();
+  let disabled_plugins = plugins::disabled_plugins(app)?;
   let plugins = get_list_plugins_with_versions(app_data_dir_string.clone()).await?;
   let response = client
     .sparus(Plugins {
       repository_name: launcher_name,
       list_plugin: plugins,
+      disabled_plugins,
     })
     .await?;
 
@@ -70,29 +118,67 @@ async fn start_streaming<R: Runtime>(
     // `while let Ok(Some(item))` also matched `Err(_)` as "loop is over" and
     // dropped the status without binding it, so a server-side error looked
     // exactly like a clean shutdown and the launcher went permanently silent.
-    let item = match stream.message().await {
+    let message = match future::select(pin!(stream.message()), pin!(changes.changed())).await {
+      Either::Left((message, _)) => message,
+      // The caller connects again, with the current plugins.
+      Either::Right(_) => return Ok(()),
+    };
+    let item = match message {
       Ok(Some(item)) => item,
       Ok(None) => return Ok(()),
       Err(status) => return Err(SparusError::Status(status)),
     };
 
-    let plugin_name = item.plugin;
+    let plugin_name = item.plugin.clone();
+    // The name is joined into the paths of the plugin's files.
+    if let Err(err) = plugins::check_plugin_name(&plugin_name) {
+      report(app, err);
+      continue;
+    }
     let url = format!("{}/plugins/{}", plugins_url, plugin_name);
     // A failure on one plugin is reported to the frontend and skipped. It must
     // not leave the loop: returning here ends the subscription, so the next
     // event never arrives (#1060).
     match EventType::try_from(item.event_type) {
       Ok(EventType::Install) | Ok(EventType::Update) => {
-        if let Err(err) = download_and_write_file(app_data_dir.clone(), url, &plugin_name).await {
-          report(
+        // The CMS may not know yet that the plugin was disabled.
+        match plugins::disabled_plugins(app) {
+          Ok(disabled) if disabled.contains(&plugin_name) => continue,
+          Ok(_) => {}
+          Err(err) => {
+            report(app, err);
+            continue;
+          }
+        }
+        let limiter = app.state::<BandwidthLimiter>().inner().clone();
+        let install = match plugin_public_keys(app) {
+          Ok(public_keys) => {
+            download_and_write_file(
+              app_data_dir.clone(),
+              url,
+              &item,
+              &app.package_info().version,
+              &public_keys,
+              &limiter,
+            )
+            .await
+          }
+          Err(err) => Err(err),
+        };
+        match install {
+          Ok(()) => {}
+          Err(err @ SparusError::PluginSignature(_)) => report(app, err),
+          Err(err) => report(
             app,
             SparusError::PluginEvent(format!(
               "Plugin {plugin_name}: install/update failed: {err}"
             )),
-          );
+          ),
         }
       }
       Ok(EventType::Delete) => {
+        let _ =
+          fs::remove_dir_all(app_data_dir.join(PLUGIN_DOWNLOADS_DIR).join(&plugin_name)).await;
         if let Err(err) =
           fs::remove_dir_all(format!("{app_data_dir_string}/plugins/{plugin_name}")).await
         {
@@ -112,6 +198,8 @@ async fn start_streaming<R: Runtime>(
         );
       }
     }
+    // The next call starts from the new files, or fails for a deleted plugin.
+    app.state::<PluginSystem>().invalidate(&plugin_name);
   }
 }
 
@@ -160,48 +248,371 @@ pub async fn start_rpc_client<R: Runtime>(
   }
 }
 
+/// The keys plugins must be signed with. Once one is configured, unsigned
+/// plugins are refused; plugins are only installed unsigned when there are
+/// none at all.
+fn plugin_public_keys<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<PublicKey>, SparusError> {
+  let mut keys: Vec<String> = BUILT_IN_PLUGIN_KEYS
+    .unwrap_or_default()
+    .split(|c: char| c == ',' || c.is_whitespace())
+    .filter(|key| !key.is_empty())
+    .map(str::to_string)
+    .collect();
+  if let Some(keys_json) = settings::bundled_config(app)?.remove(PLUGIN_KEYS_KEY) {
+    keys.extend(serde_json::from_value::<Vec<String>>(keys_json)?);
+  }
+  keys
+    .iter()
+    .map(|key| {
+      PublicKey::from_base64(key).map_err(|err| {
+        SparusError::PluginSignature(format!("Invalid plugin public key {key}: {err}"))
+      })
+    })
+    .collect()
+}
+
+/// Downloads the files of a plugin, and replaces the installed plugin
+/// directory as a whole only once they are all complete and verified, so a
+/// failed download leaves the previous version of the plugin intact.
+///
+/// With `public_keys` configured, every file must also carry a signature from
+/// one of them. `frontend.js` runs in the launcher webview, and the manifest
+/// declares the capabilities of the plugin, so both are held to the same rule
+/// as the `.wasm`.
 async fn download_and_write_file(
   app_data_dir: PathBuf,
   url: String,
-  plugin_name: &str,
+  item: &Message,
+  launcher_version: &Version,
+  public_keys: &[PublicKey],
+  limiter: &BandwidthLimiter,
 ) -> Result<(), SparusError> {
-  let plugin_name_dir = app_data_dir.join("plugins").join(plugin_name);
-  fs::create_dir_all(&plugin_name_dir).await?;
+  let plugin_name = &item.plugin;
+  let plugins_dir = app_data_dir.join("plugins");
+  let plugin_name_dir = plugins_dir.join(plugin_name);
+  // Parts are kept here across launches so that downloads resume.
+  let download_dir = app_data_dir.join(PLUGIN_DOWNLOADS_DIR).join(plugin_name);
+  fs::create_dir_all(&plugins_dir).await?;
+  fs::create_dir_all(&download_dir).await?;
 
-  let mut entries = fs::read_dir(&plugin_name_dir).await?;
-  while let Some(entries) = entries.next_entry().await? {
-    let path = entries.path();
-    if path.extension().and_then(|e| e.to_str()) == Some("wasm") {
-      fs::remove_file(path).await?;
-    }
-  }
-  download_to(
-    url.clone(),
-    plugin_name_dir.join(format!("{plugin_name}.wasm")),
+  let wasm = download_dir.join(format!("{plugin_name}.wasm"));
+  let frontend = download_dir.join("frontend.js");
+  let manifest = download_dir.join(MANIFEST_FILE);
+  let wasm_expected = Expected::new(&item.wasm_sha256, item.wasm_size);
+  let frontend_expected = Expected::new(&item.frontend_sha256, item.frontend_size);
+  let manifest_expected = Expected::new(&item.manifest_sha256, item.manifest_size);
+  remove_superseded_parts(
+    &download_dir,
+    &[
+      part_path(&wasm, &wasm_expected),
+      part_path(&frontend, &frontend_expected),
+      part_path(&manifest, &manifest_expected),
+    ],
   )
   .await?;
-  download_to(
+
+  let wasm_part = download_to(url.clone(), &wasm, wasm_expected, limiter).await?;
+  let frontend_part = download_to(
     format!("{url}/frontend.js"),
-    plugin_name_dir.join("frontend.js"),
+    &frontend,
+    frontend_expected,
+    limiter,
+  )
+  .await?;
+  let manifest_part = download_to(
+    format!("{url}/{MANIFEST_FILE}"),
+    &manifest,
+    manifest_expected,
+    limiter,
   )
   .await?;
 
+  if !public_keys.is_empty() {
+    for (destination, part, signature) in [
+      (&wasm, &wasm_part, &item.wasm_signature),
+      (&frontend, &frontend_part, &item.frontend_signature),
+      (&manifest, &manifest_part, &item.manifest_signature),
+    ] {
+      if let Err(err) = verify_signature(destination, part, signature, public_keys).await {
+        let _ = fs::remove_file(part).await;
+        return Err(SparusError::PluginSignature(format!(
+          "Plugin {plugin_name}: {err}"
+        )));
+      }
+    }
+  }
+
+  let plugin_manifest: PluginManifest = serde_json::from_slice(&fs::read(&manifest_part).await?)?;
+  if plugin_manifest.name != *plugin_name {
+    return Err(SparusError::PluginInternal(format!(
+      "{MANIFEST_FILE} describes {} instead",
+      plugin_manifest.name
+    )));
+  }
+  if !plugin_manifest.is_compatible(launcher_version) {
+    return Err(SparusError::PluginInternal(format!(
+      "version {} requires a newer launcher",
+      plugin_manifest.version
+    )));
+  }
+
+  // The new version is assembled in a directory of its own, then swapped with
+  // the installed one. Legacy `.wasm` files named after their version go away
+  // with the old directory.
+  let staged = download_dir.join("staged");
+  let _ = fs::remove_dir_all(&staged).await;
+  fs::create_dir_all(&staged).await?;
+  for (part, destination) in [
+    (&wasm_part, &wasm),
+    (&frontend_part, &frontend),
+    (&manifest_part, &manifest),
+  ] {
+    if let Some(file_name) = destination.file_name() {
+      fs::rename(part, staged.join(file_name)).await?;
+    }
+  }
+  swap_dir(&staged, &plugin_name_dir, &download_dir.join("previous")).await?;
+  let _ = fs::remove_dir_all(&download_dir).await;
+
+  Ok(())
+}
+
+/// Replaces `destination` with `staged`, moving it to `previous` meanwhile. All
+/// three are on the same file system, and `destination` is back in place if
+/// `staged` can't take it.
+async fn swap_dir(staged: &Path, destination: &Path, previous: &Path) -> Result<(), SparusError> {
+  if !destination.exists() {
+    fs::rename(staged, destination).await?;
+    return Ok(());
+  }
+
+  let _ = fs::remove_dir_all(previous).await;
+  fs::rename(destination, previous).await?;
+  if let Err(err) = fs::rename(staged, destination).await {
+    fs::rename(previous, destination).await?;
+    return Err(err.into());
+  }
+  let _ = fs::remove_dir_all(previous).await;
+  Ok(())
+}
+
+/// Removes the `.part` files of `download_dir` other than `parts`. They belong
+/// to versions of the plugin that were superseded before they finished
+/// downloading, and would never be resumed.
+async fn remove_superseded_parts(
+  download_dir: &Path,
+  parts: &[PathBuf],
+) -> Result<(), SparusError> {
+  let mut entries = fs::read_dir(download_dir).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    let path = entry.path();
+    if path.extension().and_then(|e| e.to_str()) == Some("part") && !parts.contains(&path) {
+      fs::remove_file(path).await?;
+    }
+  }
   Ok(())
 }
 
-async fn download_to(url: String, destination: PathBuf) -> Result<(), SparusError> {
-  let response = reqwest::get(url).await?;
-  if response.status() != StatusCode::OK {
-    return Err(SparusError::Plugin);
+/// Checks that the file downloaded to `part` for `destination` was signed by
+/// one of `public_keys`, and returns why not otherwise.
+async fn verify_signature(
+  destination: &Path,
+  part: &Path,
+  signature: &str,
+  public_keys: &[PublicKey],
+) -> Result<(), String> {
+  let file_name = destination
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  if signature.is_empty() {
+    return Err(format!("{file_name} is not signed"));
   }
+  let content = fs::read(part)
+    .await
+    .map_err(|err| format!("{file_name}: {err}"))?;
+  check_signature(&file_name, &content, signature, public_keys)
+}
+
+/// Checks that `content`, the content of `file_name`, carries the minisign
+/// `signature` of one of `public_keys`, and returns why not otherwise.
+pub fn check_signature(
+  file_name: &str,
+  content: &[u8],
+  signature: &str,
+  public_keys: &[PublicKey],
+) -> Result<(), String> {
+  let signature =
+    Signature::decode(signature).map_err(|err| format!("{file_name}: invalid signature: {err}"))?;
+  if public_keys
+    .iter()
+    .any(|public_key| public_key.verify(content, &signature, true).is_ok())
+  {
+    Ok(())
+  } else {
+    Err(format!("{file_name} is not signed by a trusted key"))
+  }
+}
+
+/// What the CMS says a downloaded file must be.
+struct Expected<'a> {
+  sha256: Option<&'a str>,
+  size: Option<u64>,
+}
+
+/// The `.part` file `destination` is downloaded to. It is named after the
+/// expected checksum, so a later launch resumes it only for the same file.
+fn part_path(destination: &Path, expected: &Expected<'_>) -> PathBuf {
+  let file_name = destination
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  match expected.sha256 {
+    Some(sha256) => destination.with_file_name(format!("{file_name}.{sha256}.part")),
+    None => destination.with_file_name(format!("{file_name}.part")),
+  }
+}
+
+impl<'a> Expected<'a> {
+  fn new(sha256: &'a str, size: u64) -> Self {
+    Self {
+      sha256: (!sha256.is_empty()).then_some(sha256),
+      size: (size > 0).then_some(size),
+    }
+  }
+}
+
+/// Downloads `url` to a `.part` file next to `destination` and returns it once
+/// it is complete and matches `expected`. Renaming it over `destination` is up
+/// to the caller.
+///
+/// An interrupted transfer is resumed with an HTTP Range request, and the
+/// checksum catches a part that doesn't match what was resumed. Without a
+/// checksum, the part is started over. Network errors and 5xx answers are
+/// retried.
+async fn download_to(
+  url: String,
+  destination: &Path,
+  expected: Expected<'_>,
+  limiter: &BandwidthLimiter,
+) -> Result<PathBuf, SparusError> {
+  const MAX_ATTEMPTS: u32 = 5;
+  const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+  let file_name = destination
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let part = part_path(destination, &expected);
+  if expected.sha256.is_none() {
+    let _ = fs::remove_file(&part).await;
+  }
+
+  let mut attempts = 0;
+  loop {
+    attempts += 1;
+    let total_size = match download_part(&url, &part, limiter).await {
+      Ok(total_size) => total_size,
+      Err(SparusError::Http(_)) if attempts < MAX_ATTEMPTS => {
+        sleep(RETRY_DELAY).await;
+        continue;
+      }
+      Err(SparusError::PluginDownloadStatus { status, .. })
+        if status >= 500 && attempts < MAX_ATTEMPTS =>
+      {
+        sleep(RETRY_DELAY).await;
+        continue;
+      }
+      Err(err) => return Err(err),
+    };
+
+    let size = fs::metadata(&part).await?.len();
+    match expected.size.or(total_size) {
+      // The connection closed early without an error.
+      Some(expected_size) if size < expected_size && attempts < MAX_ATTEMPTS => continue,
+      Some(expected_size) if size != expected_size => {
+        let _ = fs::remove_file(&part).await;
+        return Err(SparusError::PluginDownload(format!(
+          "{file_name}: expected {expected_size} bytes, downloaded {size}"
+        )));
+      }
+      _ => {}
+    }
+
+    if let Some(expected_sha256) = expected.sha256 {
+      let sha256 = sha256_file(&part).await?;
+      if !sha256.eq_ignore_ascii_case(expected_sha256) {
+        let _ = fs::remove_file(&part).await;
+        return Err(SparusError::PluginDownload(format!(
+          "{file_name}: checksum mismatch, expected {expected_sha256}, got {sha256}"
+        )));
+      }
+    }
+    return Ok(part);
+  }
+}
+
+/// Downloads the rest of `url` into `part` and returns the total size of the
+/// file, when the server tells it.
+async fn download_part(
+  url: &str,
+  part: &Path,
+  limiter: &BandwidthLimiter,
+) -> Result<Option<u64>, SparusError> {
+  let offset = match fs::metadata(part).await {
+    Ok(metadata) => metadata.len(),
+    Err(_) => 0,
+  };
+
+  let mut request = reqwest::Client::new().get(url);
+  if offset > 0 {
+    request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+  }
+  let response = request.send().await?;
+  let (mut file, total_size) = match response.status() {
+    StatusCode::PARTIAL_CONTENT => (
+      OpenOptions::new().append(true).open(part).await?,
+      response.content_length().map(|length| offset + length),
+    ),
+    // Either a first request, or a server that ignores Range.
+    StatusCode::OK => (File::create(part).await?, response.content_length()),
+    // The part is already complete.
+    StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => return Ok(None),
+    status => {
+      return Err(SparusError::PluginDownloadStatus {
+        url: url.to_string(),
+        status: status.as_u16(),
+      })
+    }
+  };
 
   let mut stream = response.bytes_stream();
-  let mut file = File::create(destination).await?;
+  let mut throttle = limiter.throttle();
+  let mut downloaded_bytes = 0;
   while let Some(chunk) = stream.next().await {
     let data = chunk?;
     file.write_all(&data).await?;
+    downloaded_bytes += data.len() as u64;
+    if let Some(delay) = throttle.delay(downloaded_bytes) {
+      sleep(delay).await;
+    }
   }
-  Ok(())
+  file.sync_all().await?;
+  Ok(total_size)
+}
+
+async fn sha256_file(path: &Path) -> Result<String, SparusError> {
+  let mut file = File::open(path).await?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0; 64 * 1024];
+  loop {
+    let read = file.read(&mut buffer).await?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(hex::encode(hasher.finalize()))
 }
 
 async fn get_list_plugins_with_versions(
@@ -222,6 +633,13 @@ async fn get_list_plugins_with_versions(
 
       let plugin_dir = entry.path();
 
+      if let Some(manifest) = plugins::read_manifest(&plugin_dir).await? {
+        list_plugins.insert(plugin_name, manifest.version.to_string());
+        continue;
+      }
+
+      // Plugins installed before manifests existed carry their version in
+      // their file name.
       for file in std::fs::read_dir(&plugin_dir)? {
         let file = file?;
         let path = file.path();
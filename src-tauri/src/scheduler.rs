@@ -0,0 +1,158 @@
+use crate::{
+  errors::SparusError,
+  library::{self, UpdateTarget},
+  updater::{self, LocalSpawner},
+  utils::RunningGames,
+};
+use std::collections::HashSet;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_store::StoreExt;
+use tokio::time::{sleep, Duration};
+
+/// Event carrying a `SparusError` from a background update check, which has
+/// no command to return it through.
+pub const UPDATE_ERROR_EVENT: &str = "sparus://updateerror";
+/// Event carrying the ids of the games that have an update waiting. The game
+/// installed without a library entry goes by its workspace path, the id
+/// `update_workspace` gives its updates.
+pub const PENDING_UPDATES_EVENT: &str = "sparus://pendingupdates";
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+fn report<R: Runtime>(app: &AppHandle<R>, err: SparusError) {
+  if let Ok(payload) = serde_json::to_value(&err) {
+    let _ = app.emit(UPDATE_ERROR_EVENT, payload);
+  }
+}
+
+fn notify<R: Runtime>(app: &AppHandle<R>, title: &str, body: String) {
+  let _ = app.notification().builder().title(title).body(body).show();
+}
+
+/// Checks every game of the library, and the game installed without a library
+/// entry, for updates, for as long as the app runs.
+///
+/// Runs next to the frontend checks so that updates are found, and applied
+/// when `auto_update` is enabled in the store, while the window is hidden in
+/// the tray. The interval is read from `update_check_interval`, in seconds,
+/// before each wait so that changing it doesn't need a restart.
+pub async fn start_update_scheduler<R: Runtime>(app: AppHandle<R>) {
+  // A game is only notified once per pending update, not at every check.
+  let mut notified = HashSet::new();
+  loop {
+    sleep(check_interval(&app)).await;
+    if let Err(err) = check_games(&app, &mut notified).await {
+      report(&app, err);
+    }
+  }
+}
+
+fn check_interval<R: Runtime>(app: &AppHandle<R>) -> Duration {
+  let Ok(store) = app.store("Sparus.json") else {
+    return DEFAULT_CHECK_INTERVAL;
+  };
+  match store.get("update_check_interval") {
+    Some(tauri_plugin_store::JsonValue::Number(seconds)) => seconds
+      .as_u64()
+      .filter(|seconds| *seconds > 0)
+      .map(Duration::from_secs)
+      .unwrap_or(DEFAULT_CHECK_INTERVAL),
+    _ => DEFAULT_CHECK_INTERVAL,
+  }
+}
+
+async fn check_games<R: Runtime>(
+  app: &AppHandle<R>,
+  notified: &mut HashSet<String>,
+) -> Result<(), SparusError> {
+  let store = app.store("Sparus.json")?;
+  let auto_update = matches!(
+    store.get("auto_update"),
+    Some(tauri_plugin_store::JsonValue::Bool(true))
+  );
+
+  // Each game is checked under the id its updates run with, and named by
+  // its library entry or else by the `game_name` of the store.
+  let mut targets = Vec::new();
+  for game in library::games(app)? {
+    // A broken library entry only keeps its own game from being checked.
+    match UpdateTarget::resolve(app, Some(&game.id), None, None, None) {
+      Ok(target) => targets.push((game.id, game.name, target)),
+      Err(err) => report(app, err),
+    }
+  }
+  match library::default_game_target(app) {
+    Ok(Some(target)) => {
+      let name = match store.get("game_name") {
+        Some(tauri_plugin_store::JsonValue::String(game_name)) => game_name,
+        _ => "the game".to_string(),
+      };
+      targets.push((target.workspace_path.display().to_string(), name, target));
+    }
+    Ok(None) => {}
+    Err(err) => report(app, err),
+  }
+
+  let mut pending = Vec::new();
+  let mut pending_names = Vec::new();
+  // Pinned games are skipped by `is_update_available`.
+  for (id, name, target) in targets {
+    match updater::is_update_available(app, &target).await {
+      Ok(true) => {}
+      Ok(false) => {
+        notified.remove(&id);
+        continue;
+      }
+      Err(err) => {
+        report(app, err);
+        continue;
+      }
+    }
+
+    // A running game is only offered the update, its files must not change
+    // under it.
+    let running = target
+      .workspace_path
+      .canonicalize()
+      .is_ok_and(|workspace| app.state::<RunningGames>().any_running_in(&workspace));
+    if auto_update && !running {
+      if let Some(window) = app.get_webview_window("main") {
+        let spawner = app.state::<LocalSpawner<R>>();
+        let update = updater::run_update(
+          window.as_ref().window(),
+          &spawner,
+          &target,
+          None,
+          id.clone(),
+        )
+        .await;
+        match update {
+          Ok(()) => {
+            notified.remove(&id);
+            notify(app, "Game updated", format!("{name} is up-to-date"));
+            continue;
+          }
+          Err(err) => report(app, err),
+        }
+      }
+    }
+
+    if notified.insert(id.clone()) {
+      notify(
+        app,
+        "Update available !",
+        format!("An update is available for {name}"),
+      );
+    }
+    pending.push(id);
+    pending_names.push(name);
+  }
+
+  let _ = app.emit(PENDING_UPDATES_EVENT, &pending);
+  #[cfg(desktop)]
+  crate::tray::set_pending_updates(app, &pending_names)?;
+  #[cfg(mobile)]
+  let _ = pending_names;
+  Ok(())
+}
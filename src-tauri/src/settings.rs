@@ -0,0 +1,127 @@
+use crate::errors::SparusError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{io::ErrorKind, path::PathBuf};
+use tauri::{command, AppHandle, Manager, Runtime};
+#[cfg(desktop)]
+use tauri_plugin_dialog::DialogExt;
+#[cfg(mobile)]
+use tauri_plugin_fs::FsExt;
+use tokio::fs;
+#[cfg(desktop)]
+use tokio::sync::oneshot;
+
+/// The launcher configuration bundled with the app, copied into the store on
+/// first run.
+const BUNDLED_CONFIG_FILE: &str = "Sparus.json";
+
+/// File of the app data dir holding the settings the webview must not be able
+/// to change. Like `plugin_permissions.json`, it holds a list, so that the
+/// store plugin, which only reads and writes objects, can't be pointed at it.
+const SETTINGS_FILE: &str = "launcher_settings.json";
+
+/// Settings only the launcher writes, after the user chose them in a native
+/// dialog.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+  /// Directory the games are installed in. Nothing outside of it is ever
+  /// deleted or run.
+  #[serde(default)]
+  pub workspace_root: Option<PathBuf>,
+}
+
+/// Reads the settings. The file is small, so callers that can't await read it
+/// too.
+pub fn load<R: Runtime>(app: &AppHandle<R>) -> Result<Settings, SparusError> {
+  let path = app.path().app_data_dir()?.join(SETTINGS_FILE);
+  match std::fs::read(&path) {
+    Ok(content) => {
+      let settings: Vec<Settings> = serde_json::from_slice(&content)?;
+      Ok(settings.into_iter().next().unwrap_or_default())
+    }
+    Err(err) if err.kind() == ErrorKind::NotFound => Ok(Settings::default()),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// The `Sparus.json` bundled in the resource directory, as it was built. The
+/// store starts as a copy of it, but only this one is out of reach of the
+/// webview, so what decides what runs on the machine is read from here.
+pub fn bundled_config<R: Runtime>(app: &AppHandle<R>) -> Result<Map<String, Value>, SparusError> {
+  #[cfg(desktop)]
+  let content = std::fs::read_to_string(app.path().resource_dir()?.join(BUNDLED_CONFIG_FILE))?;
+  #[cfg(mobile)]
+  let content = {
+    let resource_file = app
+      .path()
+      .resolve(BUNDLED_CONFIG_FILE, tauri::path::BaseDirectory::Resource)?;
+    app.fs().read_to_string(&resource_file)?
+  };
+  Ok(serde_json::from_str(&content)?)
+}
+
+async fn save<R: Runtime>(app: &AppHandle<R>, settings: Settings) -> Result<(), SparusError> {
+  let app_data_dir = app.path().app_data_dir()?;
+  fs::create_dir_all(&app_data_dir).await?;
+  // Written aside and renamed, so that a crash can't leave a truncated file.
+  let path = app_data_dir.join(SETTINGS_FILE);
+  let part = path.with_extension("json.part");
+  fs::write(&part, serde_json::to_vec_pretty(&[settings])?).await?;
+  fs::rename(part, path).await?;
+  Ok(())
+}
+
+#[command]
+pub fn get_workspace_root<R: Runtime>(app: AppHandle<R>) -> Result<Option<String>, SparusError> {
+  Ok(
+    load(&app)?
+      .workspace_root
+      .map(|root| root.display().to_string()),
+  )
+}
+
+/// Lets the user choose the directory the games are installed in, in a native
+/// folder picker. The webview can open the picker, but only the user picks the
+/// directory. Returns the new root, or `None` when the user cancelled.
+#[cfg(desktop)]
+#[command]
+pub async fn choose_workspace_root<R: Runtime>(
+  app: AppHandle<R>,
+) -> Result<Option<String>, SparusError> {
+  let (sender, receiver) = oneshot::channel();
+  app
+    .dialog()
+    .file()
+    .set_title("Installation folder")
+    .pick_folder(move |folder| {
+      let _ = sender.send(folder);
+    });
+  let Some(folder) = receiver.await.ok().flatten() else {
+    return Ok(None);
+  };
+  let root = folder
+    .into_path()
+    .map_err(|err| SparusError::Game(format!("Invalid installation folder: {err}")))?;
+  if !root.is_dir() {
+    return Err(SparusError::Game(format!(
+      "{} is not a directory",
+      root.display()
+    )));
+  }
+
+  let mut settings = load(&app)?;
+  settings.workspace_root = Some(root.clone());
+  save(&app, settings).await?;
+  Ok(Some(root.display().to_string()))
+}
+
+/// The native folder picker is only available on desktop.
+#[cfg(mobile)]
+#[command]
+pub async fn choose_workspace_root<R: Runtime>(
+  _app: AppHandle<R>,
+) -> Result<Option<String>, SparusError> {
+  Err(SparusError::Game(
+    "The installation folder can't be changed on this platform".to_string(),
+  ))
+}
@@ -29,3 +29,20 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
     .build(app);
   Ok(())
 }
+
+/// Lists the games that have an update waiting in the tray tooltip, so it
+/// shows while the window is hidden.
+pub fn set_pending_updates<R: Runtime>(
+  app: &tauri::AppHandle<R>,
+  games: &[String],
+) -> tauri::Result<()> {
+  if let Some(tray) = app.tray_by_id("tray") {
+    let tooltip = if games.is_empty() {
+      "Sparus".to_string()
+    } else {
+      format!("Sparus - update available: {}", games.join(", "))
+    };
+    tray.set_tooltip(Some(tooltip))?;
+  }
+  Ok(())
+}
@@ -1,22 +1,45 @@
-use crate::{errors::SparusError, utils};
+use crate::{
+  bandwidth::BandwidthLimiter,
+  errors::SparusError,
+  history::{self, UpdateRecord},
+  hooks::{self, UpdateInfo},
+  library::{self, Channel, UpdateTarget},
+  utils,
+};
 use async_lock::Mutex;
-use futures::TryStreamExt;
+use chrono::Utc;
+use futures::{
+  future::{AbortHandle, AbortRegistration, Abortable},
+  TryStreamExt,
+};
 use libspeedupdate::{
   link::{AutoRepository, RemoteRepository},
   metadata::{CleanName, Current},
-  workspace::{UpdateOptions, Workspace},
+  workspace::{UpdateOptions, Workspace, WorkspaceState},
 };
 use semver::Version;
 use serde::Serialize;
-use std::{future, path::Path, sync::Arc};
-use tauri::{command, AppHandle, Emitter, Runtime, Window};
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  env, future,
+  path::{Path, PathBuf},
+  rc::Rc,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+  },
+};
+use tauri::{command, AppHandle, Emitter, Manager, Runtime, Window};
 use tokio::{
   sync::{mpsc, oneshot},
   task::LocalSet,
+  time::sleep,
 };
 
 #[derive(Clone, Serialize)]
 struct DownloadInfos {
+  update_id: String,
   packages_start: usize,
   packages_end: usize,
 
@@ -38,16 +61,102 @@ struct DownloadInfos {
   applied_output_bytes_per_sec: Option<f64>,
 }
 
+#[derive(Clone, Serialize)]
+struct CheckInfos {
+  checked_files_start: usize,
+  checked_files_end: usize,
+  checked_bytes_start: u64,
+  checked_bytes_end: u64,
+  failed_files: usize,
+}
+
+/// Result of `verify_workspace`: the installed version and the files that no
+/// longer match it, as paths relative to the workspace.
+#[derive(Default, Serialize)]
+pub struct WorkspaceReport {
+  version: Option<String>,
+  missing: Vec<String>,
+  modified: Vec<String>,
+}
+
 pub enum Task<R: Runtime> {
   UpdateWorkspace {
+    update_id: String,
+    game_id: Option<String>,
     window: Window<R>,
     repo: AutoRepository,
+    workspace_path: PathBuf,
     workspace: Arc<Mutex<Workspace>>,
     goal_version: Option<String>,
+    stats: Arc<UpdateStats>,
+    response: oneshot::Sender<Result<(), SparusError>>,
+  },
+  CheckWorkspace {
+    window: Window<R>,
+    workspace_path: PathBuf,
+    workspace: Arc<Mutex<Workspace>>,
+    response: oneshot::Sender<Result<WorkspaceReport, SparusError>>,
+  },
+  CancelUpdate {
+    update_id: String,
+    response: oneshot::Sender<Result<(), SparusError>>,
+  },
+  PauseUpdate {
+    update_id: String,
+    response: oneshot::Sender<Result<(), SparusError>>,
+  },
+  ResumeUpdate {
+    update_id: String,
     response: oneshot::Sender<Result<(), SparusError>>,
   },
+  /// Finds the update, running or paused, of the workspace at
+  /// `workspace_path`, a canonical path.
+  FindUpdate {
+    workspace_path: PathBuf,
+    response: oneshot::Sender<Option<String>>,
+  },
+}
+
+/// Totals of an update across its pause and resume runs, for the history.
+#[derive(Default)]
+pub struct UpdateStats {
+  downloaded_bytes: AtomicU64,
+  failed_files: AtomicUsize,
+}
+
+enum UpdateStatus {
+  Running(AbortHandle),
+  Paused,
+}
+
+/// An update known to the `LocalSpawner` thread, from the moment it is queued
+/// until it completes or is cancelled.
+///
+/// Pausing only aborts the update stream. libspeedupdate persists its progress
+/// in `.update/state.json` as it goes, so resuming runs `Workspace::update`
+/// again with the same arguments and picks up from what was already applied.
+struct RunningUpdate<R: Runtime> {
+  window: Window<R>,
+  repo: Rc<AutoRepository>,
+  workspace_path: PathBuf,
+  workspace: Arc<Mutex<Workspace>>,
+  goal_version: Option<CleanName>,
+  stats: Arc<UpdateStats>,
+  /// What the update hooks of the plugins are called with.
+  hook_info: UpdateInfo,
+  /// Whether the `before_update` hooks returned. A cancel that comes earlier
+  /// doesn't call the `after_update` hooks, for an update the plugins aren't
+  /// done preparing for.
+  before_update_done: bool,
+  /// Whether the free disk space was checked. It is only checked once per
+  /// update, a resumed run doesn't check it again.
+  space_checked: bool,
+  response: oneshot::Sender<Result<(), SparusError>>,
+  status: UpdateStatus,
 }
 
+type Updates<R> = Rc<RefCell<HashMap<String, RunningUpdate<R>>>>;
+
 #[derive(Clone)]
 pub struct LocalSpawner<R: Runtime> {
   send: mpsc::UnboundedSender<Task<R>>,
@@ -59,10 +168,11 @@ impl<R: Runtime> LocalSpawner<R> {
 
     std::thread::spawn(move || {
       let local = LocalSet::new();
+      let updates: Updates<R> = Rc::new(RefCell::new(HashMap::new()));
 
       local.spawn_local(async move {
         while let Some(new_task) = recv.recv().await {
-          tokio::task::spawn_local(run_task(new_task));
+          tokio::task::spawn_local(run_task(new_task, updates.clone()));
         }
       });
 
@@ -79,15 +189,24 @@ impl<R: Runtime> LocalSpawner<R> {
   }
 }
 
-async fn run_task<R: Runtime>(task: Task<R>) {
+async fn run_task<R: Runtime>(task: Task<R>, updates: Updates<R>) {
   match task {
     Task::UpdateWorkspace {
+      update_id,
+      game_id,
       window,
       repo,
+      workspace_path,
       workspace,
       goal_version,
+      stats,
       response,
     } => {
+      let hook_info = UpdateInfo {
+        game_id,
+        workspace_path: workspace_path.display().to_string(),
+        goal_version: goal_version.clone(),
+      };
       let goal_version = match goal_version.map(CleanName::new).transpose() {
         Ok(goal) => goal,
         Err(invalid) => {
@@ -98,68 +217,659 @@ async fn run_task<R: Runtime>(task: Task<R>) {
         }
       };
 
-      let result = workspace
-        .lock()
-        .await
-        .update(&repo, goal_version, UpdateOptions::default())
-        .try_take_while(|progress| {
-          let state = progress.borrow();
-          let progression = state.histogram.progress();
-          let speed = state.histogram.speed().progress_per_sec();
-          let _ = window.emit(
-            "sparus://downloadinfos",
-            DownloadInfos {
-              packages_start: state.downloading_package_idx,
-              packages_end: state.steps.len(),
-              downloaded_files_start: Some(progression.downloaded_files),
-              downloaded_files_end: Some(state.download_files),
-              downloaded_bytes_start: Some(progression.downloaded_bytes),
-              downloaded_bytes_end: Some(state.download_bytes),
-              applied_files_start: Some(progression.applied_files),
-              applied_files_end: Some(state.apply_files),
-              applied_input_bytes_start: Some(progression.applied_input_bytes),
-              applied_input_bytes_end: Some(state.apply_input_bytes),
-              applied_output_bytes_start: Some(progression.applied_output_bytes),
-              applied_output_bytes_end: Some(state.apply_output_bytes),
-              failed_files: Some(progression.failed_files),
-              downloaded_files_per_sec: Some(speed.downloaded_files_per_sec),
-              downloaded_bytes_per_sec: Some(speed.downloaded_bytes_per_sec),
-              applied_files_per_sec: Some(speed.applied_files_per_sec),
-              applied_input_bytes_per_sec: Some(speed.applied_input_bytes_per_sec),
-              applied_output_bytes_per_sec: Some(speed.applied_output_bytes_per_sec),
-            },
-          );
-          future::ready(Ok(true))
+      if updates.borrow().contains_key(&update_id) {
+        let _ = response.send(Err(SparusError::Update(format!(
+          "update {update_id} is already running"
+        ))));
+        return;
+      }
+
+      let (abort_handle, abort_registration) = AbortHandle::new_pair();
+      let app = window.app_handle().clone();
+      updates.borrow_mut().insert(
+        update_id.clone(),
+        RunningUpdate {
+          window,
+          repo: Rc::new(repo),
+          workspace_path,
+          workspace,
+          goal_version,
+          stats,
+          hook_info: hook_info.clone(),
+          before_update_done: false,
+          space_checked: false,
+          response,
+          status: UpdateStatus::Running(abort_handle),
+        },
+      );
+      // Registered first, so that the update can already be cancelled while
+      // the plugins run.
+      hooks::before_update(&app, &hook_info).await;
+      if let Some(update) = updates.borrow_mut().get_mut(&update_id) {
+        update.before_update_done = true;
+      }
+      drive_update(update_id, updates, abort_registration).await;
+    }
+    Task::CheckWorkspace {
+      window,
+      workspace_path,
+      workspace,
+      response,
+    } => {
+      let _ = response.send(check_workspace(window, &workspace_path, workspace).await);
+    }
+    Task::CancelUpdate {
+      update_id,
+      response,
+    } => {
+      let removed = updates.borrow_mut().remove(&update_id);
+      match removed {
+        Some(update) => {
+          if let UpdateStatus::Running(abort_handle) = update.status {
+            abort_handle.abort();
+          }
+          let _ = response.send(Ok(()));
+          let cancelled = SparusError::UpdateCancelled(update_id);
+          if update.before_update_done {
+            hooks::after_update(
+              update.window.app_handle(),
+              &update.hook_info,
+              Some(&cancelled),
+            )
+            .await;
+          }
+          let _ = update.response.send(Err(cancelled));
+        }
+        None => {
+          let _ = response.send(Err(SparusError::UpdateNotFound(update_id)));
+        }
+      }
+    }
+    Task::PauseUpdate {
+      update_id,
+      response,
+    } => {
+      let result = match updates.borrow_mut().get_mut(&update_id) {
+        Some(update) => {
+          if let UpdateStatus::Running(abort_handle) = &update.status {
+            abort_handle.abort();
+          }
+          update.status = UpdateStatus::Paused;
+          Ok(())
+        }
+        None => Err(SparusError::UpdateNotFound(update_id)),
+      };
+      let _ = response.send(result);
+    }
+    Task::ResumeUpdate {
+      update_id,
+      response,
+    } => {
+      let abort_registration = match updates.borrow_mut().get_mut(&update_id) {
+        Some(update) => match update.status {
+          UpdateStatus::Paused => {
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            update.status = UpdateStatus::Running(abort_handle);
+            Ok(abort_registration)
+          }
+          UpdateStatus::Running(_) => Err(SparusError::Update(format!(
+            "update {update_id} is not paused"
+          ))),
+        },
+        None => Err(SparusError::UpdateNotFound(update_id.clone())),
+      };
+      match abort_registration {
+        Ok(abort_registration) => {
+          let _ = response.send(Ok(()));
+          drive_update(update_id, updates, abort_registration).await;
+        }
+        Err(err) => {
+          let _ = response.send(Err(err));
+        }
+      }
+    }
+    Task::FindUpdate {
+      workspace_path,
+      response,
+    } => {
+      let update_id = updates
+        .borrow()
+        .iter()
+        .find(|(_, update)| {
+          update
+            .workspace_path
+            .canonicalize()
+            .is_ok_and(|path| path == workspace_path)
         })
-        .try_for_each(|_| future::ready(Ok(())))
-        .await;
+        .map(|(update_id, _)| update_id.clone());
+      let _ = response.send(update_id);
+    }
+  }
+}
 
-      let _ = response.send(result.map_err(Into::into));
+async fn check_workspace<R: Runtime>(
+  window: Window<R>,
+  workspace_path: &Path,
+  workspace: Arc<Mutex<Workspace>>,
+) -> Result<WorkspaceReport, SparusError> {
+  let mut workspace = workspace.lock().await;
+  workspace
+    .check()
+    .try_for_each(|progress| {
+      let state = progress.borrow();
+      let _ = window.emit(
+        "sparus://checkinfos",
+        CheckInfos {
+          checked_files_start: state.checked_files,
+          checked_files_end: state.check_files,
+          checked_bytes_start: state.checked_bytes,
+          checked_bytes_end: state.check_bytes,
+          failed_files: state.failed_files,
+        },
+      );
+      future::ready(Ok(()))
+    })
+    .await?;
+
+  let mut report = WorkspaceReport::default();
+  match workspace.state() {
+    WorkspaceState::Stable { version } => report.version = Some(version.to_string()),
+    WorkspaceState::Corrupted { version, failures } => {
+      report.version = Some(version.to_string());
+      for failure in failures {
+        if workspace_path.join(failure).exists() {
+          report.modified.push(failure.to_string());
+        } else {
+          report.missing.push(failure.to_string());
+        }
+      }
     }
+    _ => {}
   }
+  Ok(report)
+}
+
+/// Runs `Workspace::update` for a registered update until it finishes or is
+/// aborted by a pause or a cancel.
+///
+/// The entry stays in `updates` while paused so that it can be resumed, and
+/// the caller of `update_workspace` only gets its response once the update
+/// really completes or is cancelled.
+async fn drive_update<R: Runtime>(
+  update_id: String,
+  updates: Updates<R>,
+  abort_registration: AbortRegistration,
+) {
+  let Some((window, repo, workspace_path, workspace, goal_version, stats, space_checked)) =
+    updates.borrow().get(&update_id).map(|update| {
+      (
+        update.window.clone(),
+        update.repo.clone(),
+        update.workspace_path.clone(),
+        update.workspace.clone(),
+        update.goal_version.clone(),
+        update.stats.clone(),
+        update.space_checked,
+      )
+    })
+  else {
+    return;
+  };
+
+  // A resumed run counts from zero again.
+  let previous_bytes = stats.downloaded_bytes.load(Ordering::Relaxed);
+  let previous_failed_files = stats.failed_files.load(Ordering::Relaxed);
+
+  let mut space_checked = space_checked;
+  let mut throttle = window.state::<BandwidthLimiter>().throttle();
+  let update = async {
+    workspace
+      .lock()
+      .await
+      .update(repo.as_ref(), goal_version, UpdateOptions::default())
+      .map_err(SparusError::from)
+      .try_take_while(|progress| {
+        let state = progress.borrow();
+        let progression = state.histogram.progress();
+        // The sizes are known once the update path has been planned, which is
+        // before anything is downloaded or written. Only what is left to
+        // download and write has to fit.
+        let mut preflight = Ok(());
+        if !space_checked && !state.steps.is_empty() {
+          space_checked = true;
+          if let Some(update) = updates.borrow_mut().get_mut(&update_id) {
+            update.space_checked = true;
+          }
+          preflight = check_disk_space(
+            &workspace_path,
+            state
+              .download_bytes
+              .saturating_sub(progression.downloaded_bytes),
+            state
+              .apply_output_bytes
+              .saturating_sub(progression.applied_output_bytes),
+          );
+        }
+        let speed = state.histogram.speed().progress_per_sec();
+        stats.downloaded_bytes.store(
+          previous_bytes + progression.downloaded_bytes,
+          Ordering::Relaxed,
+        );
+        stats.failed_files.store(
+          previous_failed_files + progression.failed_files,
+          Ordering::Relaxed,
+        );
+        let _ = window.emit(
+          "sparus://downloadinfos",
+          DownloadInfos {
+            update_id: update_id.clone(),
+            packages_start: state.downloading_package_idx,
+            packages_end: state.steps.len(),
+            downloaded_files_start: Some(progression.downloaded_files),
+            downloaded_files_end: Some(state.download_files),
+            downloaded_bytes_start: Some(progression.downloaded_bytes),
+            downloaded_bytes_end: Some(state.download_bytes),
+            applied_files_start: Some(progression.applied_files),
+            applied_files_end: Some(state.apply_files),
+            applied_input_bytes_start: Some(progression.applied_input_bytes),
+            applied_input_bytes_end: Some(state.apply_input_bytes),
+            applied_output_bytes_start: Some(progression.applied_output_bytes),
+            applied_output_bytes_end: Some(state.apply_output_bytes),
+            failed_files: Some(progression.failed_files),
+            downloaded_files_per_sec: Some(speed.downloaded_files_per_sec),
+            downloaded_bytes_per_sec: Some(speed.downloaded_bytes_per_sec),
+            applied_files_per_sec: Some(speed.applied_files_per_sec),
+            applied_input_bytes_per_sec: Some(speed.applied_input_bytes_per_sec),
+            applied_output_bytes_per_sec: Some(speed.applied_output_bytes_per_sec),
+          },
+        );
+        // libspeedupdate only downloads while its stream is polled, so
+        // holding the next poll back is what enforces the bandwidth limit.
+        let delay = throttle.delay(progression.downloaded_bytes);
+        async move {
+          preflight?;
+          if let Some(delay) = delay {
+            sleep(delay).await;
+          }
+          Ok(true)
+        }
+      })
+      .try_for_each(|_| future::ready(Ok(())))
+      .await
+  };
+
+  // An aborted update was paused or cancelled. Either way the `Task` that
+  // aborted it already took care of the entry and of the response.
+  let Ok(result) = Abortable::new(update, abort_registration).await else {
+    return;
+  };
+
+  let removed = updates.borrow_mut().remove(&update_id);
+  if let Some(update) = removed {
+    hooks::after_update(
+      update.window.app_handle(),
+      &update.hook_info,
+      result.as_ref().err(),
+    )
+    .await;
+    let _ = update.response.send(result);
+  }
+}
+
+/// Fails with `SparusError::InsufficientSpace` when an update still needing
+/// `download_bytes` of packages and `apply_output_bytes` of game files doesn't
+/// fit, so it stops before leaving the workspace half-applied.
+///
+/// Packages are downloaded to `.update` inside the workspace. That is usually
+/// the same volume as the game files, and then both have to fit at once.
+fn check_disk_space(
+  workspace_path: &Path,
+  download_bytes: u64,
+  apply_output_bytes: u64,
+) -> Result<(), SparusError> {
+  let download_path = workspace_path.join(".update");
+  let download_path = if download_path.is_dir() {
+    download_path
+  } else {
+    workspace_path.to_path_buf()
+  };
+
+  let workspace_available = fs4::available_space(workspace_path)?;
+  if same_volume(workspace_path, &download_path) {
+    ensure_space(
+      workspace_path,
+      download_bytes + apply_output_bytes,
+      workspace_available,
+    )
+  } else {
+    ensure_space(
+      &download_path,
+      download_bytes,
+      fs4::available_space(&download_path)?,
+    )?;
+    ensure_space(workspace_path, apply_output_bytes, workspace_available)
+  }
+}
+
+fn ensure_space(path: &Path, required: u64, available: u64) -> Result<(), SparusError> {
+  if available < required {
+    return Err(SparusError::InsufficientSpace {
+      path: path.display().to_string(),
+      required,
+      available,
+    });
+  }
+  Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn same_volume(a: &Path, b: &Path) -> bool {
+  use std::os::unix::fs::MetadataExt;
+  match (a.metadata(), b.metadata()) {
+    (Ok(a), Ok(b)) => a.dev() == b.dev(),
+    _ => true,
+  }
+}
+
+// Assumes the worst case, both sizes on one volume.
+#[cfg(not(target_family = "unix"))]
+fn same_volume(_a: &Path, _b: &Path) -> bool {
+  true
 }
 
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_workspace<R: Runtime>(
   window: Window<R>,
   spawner: tauri::State<'_, LocalSpawner<R>>,
-  workspace_path: &str,
-  repository_url: &str,
-  auth: Option<(&str, &str)>,
+  game_id: Option<String>,
+  workspace_path: Option<String>,
+  repository_url: Option<String>,
+  auth: Option<(String, String)>,
   goal_version: Option<String>,
+  update_id: Option<String>,
 ) -> Result<(), SparusError> {
-  let repo = AutoRepository::new(repository_url, auth)?;
+  let target = UpdateTarget::resolve(
+    window.app_handle(),
+    game_id.as_deref(),
+    repository_url,
+    workspace_path,
+    auth,
+  )?;
+  // A workspace can only run one update at a time, so the game, or else the
+  // workspace path, is a good default id for callers that don't need to
+  // control the update.
+  let update_id = update_id
+    .or(game_id)
+    .unwrap_or_else(|| target.workspace_path.display().to_string());
+  let goal_version = goal_version.or(target.pinned_version.clone());
+
+  run_update(window, &spawner, &target, goal_version, update_id).await
+}
 
-  let workspace = Arc::new(Mutex::new(Workspace::open(Path::new(workspace_path))?));
+/// Moves a library game to another release channel, then updates or
+/// downgrades its workspace to the current version of that channel.
+#[command]
+pub async fn set_game_channel<R: Runtime>(
+  window: Window<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  game_id: String,
+  channel: Channel,
+) -> Result<(), SparusError> {
+  let handle = window.app_handle().clone();
+  let game = library::game(&handle, &game_id)?;
+  let mut target = UpdateTarget::resolve(&handle, Some(&game_id), None, None, None)?;
+  // Fails early for a channel the game doesn't publish.
+  target.repository_url = game.repository_url(channel)?.to_string();
+  target.channel = channel;
+  // A pin refers to a version of the previous channel.
+  target.pinned_version = None;
+  let goal_version = latest_remote_version(target.repository_url.clone(), target.auth())
+    .await?
+    .version()
+    .to_string();
+
+  run_update(
+    window,
+    &spawner,
+    &target,
+    Some(goal_version),
+    game_id.clone(),
+  )
+  .await?;
+
+  // Only saved once the files of the channel are installed, so that a failed
+  // switch leaves the game on the channel it really is on.
+  let mut games = library::games(&handle)?;
+  let game = games
+    .iter_mut()
+    .find(|game| game.id == game_id)
+    .ok_or(SparusError::GameNotFound(game_id))?;
+  game.channel = channel;
+  game.pinned_version = None;
+  library::save_games(&handle, &games)
+}
+
+/// Runs an update on the `LocalSpawner` thread and records it in the update
+/// history, whatever its outcome.
+pub async fn run_update<R: Runtime>(
+  window: Window<R>,
+  spawner: &LocalSpawner<R>,
+  target: &UpdateTarget,
+  goal_version: Option<String>,
+  update_id: String,
+) -> Result<(), SparusError> {
+  let handle = window.app_handle().clone();
+  let started_at = Utc::now();
+  let from_version = utils::version(handle.clone(), &target.workspace_path).ok();
+  let stats = Arc::new(UpdateStats::default());
+
+  let result = spawn_update(
+    window,
+    spawner,
+    target,
+    goal_version.clone(),
+    update_id.clone(),
+    stats.clone(),
+  )
+  .await;
+
+  let to_version = match result {
+    Ok(()) => utils::version(handle.clone(), &target.workspace_path).ok(),
+    Err(_) => goal_version,
+  };
+  let _ = history::record(
+    &handle,
+    &UpdateRecord {
+      update_id,
+      game_id: target.game_id.clone(),
+      workspace_path: target.workspace_path.display().to_string(),
+      from_version,
+      to_version,
+      started_at: started_at.to_rfc3339(),
+      ended_at: Utc::now().to_rfc3339(),
+      downloaded_bytes: stats.downloaded_bytes.load(Ordering::Relaxed),
+      failed_files: stats.failed_files.load(Ordering::Relaxed),
+      error: result
+        .as_ref()
+        .err()
+        .and_then(|err| serde_json::to_value(err).ok()),
+    },
+  );
+  result
+}
+
+async fn spawn_update<R: Runtime>(
+  window: Window<R>,
+  spawner: &LocalSpawner<R>,
+  target: &UpdateTarget,
+  goal_version: Option<String>,
+  update_id: String,
+  stats: Arc<UpdateStats>,
+) -> Result<(), SparusError> {
+  let repo = AutoRepository::new(&target.repository_url, target.auth())?;
+
+  let workspace = Arc::new(Mutex::new(Workspace::open(&target.workspace_path)?));
 
   let (send, response) = oneshot::channel();
   spawner.spawn(Task::UpdateWorkspace {
+    update_id,
+    game_id: target.game_id.clone(),
     window,
     repo,
+    workspace_path: target.workspace_path.clone(),
     workspace,
     goal_version,
+    stats,
+    response: send,
+  });
+  match response.await {
+    Ok(result) => result,
+    Err(_) => Err(SparusError::Update(
+      "update task did not return a result".to_string(),
+    )),
+  }
+}
+
+/// Id of the update, running or paused, of the workspace at `workspace_path`,
+/// a canonical path.
+pub async fn workspace_update<R: Runtime>(
+  spawner: &LocalSpawner<R>,
+  workspace_path: PathBuf,
+) -> Option<String> {
+  let (send, response) = oneshot::channel();
+  spawner.spawn(Task::FindUpdate {
+    workspace_path,
+    response: send,
+  });
+  response.await.ok().flatten()
+}
+
+/// Checks the installed files of a workspace against the manifest of its
+/// installed version, emitting `sparus://checkinfos` progress events. The
+/// workspace must be inside the workspace root.
+#[command]
+pub async fn verify_workspace<R: Runtime>(
+  window: Window<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  game_id: Option<String>,
+  workspace_path: Option<String>,
+) -> Result<WorkspaceReport, SparusError> {
+  let workspace_path = match game_id {
+    Some(game_id) => PathBuf::from(library::game(window.app_handle(), &game_id)?.workspace_path),
+    None => match workspace_path {
+      Some(workspace_path) => PathBuf::from(workspace_path),
+      None => env::current_dir()?,
+    },
+  };
+  let workspace_path = library::inside_workspace_root(window.app_handle(), &workspace_path)?;
+  run_check(window, &spawner, &workspace_path).await
+}
+
+/// Verifies a workspace, then re-downloads only the files that failed the
+/// check by updating the workspace to its installed version. The workspace
+/// must be inside the workspace root, and have an installed version, since
+/// repairing must not update it to another one.
+///
+/// Returns the report of the files that were repaired.
+#[command]
+pub async fn repair_workspace<R: Runtime>(
+  window: Window<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  game_id: Option<String>,
+  workspace_path: Option<String>,
+  repository_url: Option<String>,
+  auth: Option<(String, String)>,
+) -> Result<WorkspaceReport, SparusError> {
+  let target = UpdateTarget::resolve(
+    window.app_handle(),
+    game_id.as_deref(),
+    repository_url,
+    workspace_path,
+    auth,
+  )?;
+  library::inside_workspace_root(window.app_handle(), &target.workspace_path)?;
+  let report = run_check(window.clone(), &spawner, &target.workspace_path).await?;
+  if report.missing.is_empty() && report.modified.is_empty() {
+    return Ok(report);
+  }
+  let Some(version) = report.version.clone() else {
+    return Err(SparusError::NoVersion);
+  };
+
+  let update_id = game_id.unwrap_or_else(|| target.workspace_path.display().to_string());
+  run_update(window, &spawner, &target, Some(version), update_id).await?;
+  Ok(report)
+}
+
+async fn run_check<R: Runtime>(
+  window: Window<R>,
+  spawner: &LocalSpawner<R>,
+  workspace_path: &Path,
+) -> Result<WorkspaceReport, SparusError> {
+  let workspace = Arc::new(Mutex::new(Workspace::open(workspace_path)?));
+
+  let (send, response) = oneshot::channel();
+  spawner.spawn(Task::CheckWorkspace {
+    window,
+    workspace_path: workspace_path.to_path_buf(),
+    workspace,
     response: send,
   });
+  match response.await {
+    Ok(result) => result,
+    Err(_) => Err(SparusError::Update(
+      "check task did not return a result".to_string(),
+    )),
+  }
+}
+
+#[command]
+pub async fn cancel_update<R: Runtime>(
+  // Only there to tell which runtime `R` the spawner runs updates with.
+  _handle: AppHandle<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  update_id: String,
+) -> Result<(), SparusError> {
+  let (send, response) = oneshot::channel();
+  spawner.spawn(Task::CancelUpdate {
+    update_id,
+    response: send,
+  });
+  control_response(response).await
+}
+
+#[command]
+pub async fn pause_update<R: Runtime>(
+  _handle: AppHandle<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  update_id: String,
+) -> Result<(), SparusError> {
+  let (send, response) = oneshot::channel();
+  spawner.spawn(Task::PauseUpdate {
+    update_id,
+    response: send,
+  });
+  control_response(response).await
+}
+
+#[command]
+pub async fn resume_update<R: Runtime>(
+  _handle: AppHandle<R>,
+  spawner: tauri::State<'_, LocalSpawner<R>>,
+  update_id: String,
+) -> Result<(), SparusError> {
+  let (send, response) = oneshot::channel();
+  spawner.spawn(Task::ResumeUpdate {
+    update_id,
+    response: send,
+  });
+  control_response(response).await
+}
+
+async fn control_response(
+  response: oneshot::Receiver<Result<(), SparusError>>,
+) -> Result<(), SparusError> {
   match response.await {
     Ok(result) => result,
     Err(_) => Err(SparusError::Update(
@@ -171,22 +881,32 @@ pub async fn update_workspace<R: Runtime>(
 #[command]
 pub async fn update_available<R: Runtime>(
   handle: AppHandle<R>,
-  repository_url: String,
+  game_id: Option<String>,
+  repository_url: Option<String>,
   username: Option<String>,
   password: Option<String>,
 ) -> Result<bool, SparusError> {
-  let auth;
-  match username {
-    Some(ref user) => match password {
-      Some(ref pass) => auth = Some((user.as_str(), pass.as_str())),
-      None => auth = None,
-    },
-    None => auth = None,
+  let target = UpdateTarget::resolve(
+    &handle,
+    game_id.as_deref(),
+    repository_url,
+    None,
+    username.zip(password),
+  )?;
+  is_update_available(&handle, &target).await
+}
+
+pub async fn is_update_available<R: Runtime>(
+  handle: &AppHandle<R>,
+  target: &UpdateTarget,
+) -> Result<bool, SparusError> {
+  if target.pinned_version.is_some() {
+    return Ok(false);
   }
 
-  let local_version_string = utils::version(handle)?;
+  let local_version_string = utils::version(handle.clone(), &target.workspace_path)?;
   let local_version = Version::parse(&local_version_string)?;
-  let remote_version = latest_remote_version(repository_url, auth).await;
+  let remote_version = latest_remote_version(target.repository_url.clone(), target.auth()).await;
   match remote_version {
     Ok(value) => {
       let remote_version_semver = Version::parse(value.version().as_str());
@@ -197,7 +917,47 @@ pub async fn update_available<R: Runtime>(
   }
 }
 
-async fn latest_remote_version(
+#[derive(Serialize)]
+pub struct RemoteVersion {
+  pub version: String,
+  description: String,
+}
+
+/// Versions published by the repository, as listed in its metadata, so a
+/// player can pick one to roll back to.
+#[command]
+pub async fn list_remote_versions<R: Runtime>(
+  handle: AppHandle<R>,
+  game_id: Option<String>,
+  repository_url: Option<String>,
+  username: Option<String>,
+  password: Option<String>,
+) -> Result<Vec<RemoteVersion>, SparusError> {
+  let target = UpdateTarget::resolve(
+    &handle,
+    game_id.as_deref(),
+    repository_url,
+    None,
+    username.zip(password),
+  )?;
+  remote_versions(&target).await
+}
+
+pub async fn remote_versions(target: &UpdateTarget) -> Result<Vec<RemoteVersion>, SparusError> {
+  let repo = AutoRepository::new(&target.repository_url, target.auth())?;
+  let versions = repo.versions().await?;
+  Ok(
+    versions
+      .iter()
+      .map(|version| RemoteVersion {
+        version: version.revision().to_string(),
+        description: version.description().to_string(),
+      })
+      .collect(),
+  )
+}
+
+pub async fn latest_remote_version(
   repository_url: String,
   auth: Option<(&str, &str)>,
 ) -> Result<Current, SparusError> {
@@ -1,28 +1,106 @@
-use crate::errors::SparusError;
-use serde::Deserialize;
+use crate::{errors::SparusError, hooks, library, settings};
+use libspeedupdate::workspace::{Workspace, WorkspaceState};
+use serde::{Deserialize, Serialize};
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+  collections::HashMap,
   env,
   fs::{self, File},
-  path::Path,
+  path::{Component, Path, PathBuf},
+  process::Command,
+  sync::Mutex,
 };
-use tauri::{command, AppHandle, Runtime};
+use tauri::{command, AppHandle, Emitter, Manager, Runtime};
 use tauri_plugin_store::StoreExt;
 
-#[derive(Debug, Deserialize)]
-pub struct Root {
-  pub state: State,
+pub const GAME_STARTED_EVENT: &str = "sparus://game-started";
+pub const GAME_EXITED_EVENT: &str = "sparus://game-exited";
+
+/// How to start a game. Read from the `game_launch` key of the bundled
+/// `Sparus.json`, or from a `launch.json` shipped at the root of the game
+/// workspace by its repository.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchConfig {
+  pub executable: Option<String>,
+  #[serde(default)]
+  pub args: Vec<String>,
+  /// Arguments the frontend may add after `args` when it launches the game.
+  #[serde(default)]
+  pub allowed_args: Vec<String>,
+  #[serde(default)]
+  pub env: HashMap<String, String>,
+  pub working_dir: Option<String>,
+  /// Overrides keyed by `std::env::consts::OS` (`windows`, `linux`, `macos`).
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  pub os: HashMap<String, LaunchOverride>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchOverride {
+  pub executable: Option<String>,
+  pub args: Option<Vec<String>>,
+  pub env: Option<HashMap<String, String>>,
+  pub working_dir: Option<String>,
+}
+
+impl LaunchConfig {
+  fn for_current_os(mut self) -> Self {
+    if let Some(os_override) = self.os.remove(env::consts::OS) {
+      if os_override.executable.is_some() {
+        self.executable = os_override.executable;
+      }
+      if let Some(args) = os_override.args {
+        self.args = args;
+      }
+      if let Some(env) = os_override.env {
+        self.env.extend(env);
+      }
+      if os_override.working_dir.is_some() {
+        self.working_dir = os_override.working_dir;
+      }
+    }
+    self.os.clear();
+    self
+  }
+}
+
+#[derive(Clone, Serialize)]
+struct GameStarted {
+  executable: String,
+  pid: u32,
+}
+
+#[derive(Clone, Serialize)]
+struct GameExited {
+  executable: String,
+  pid: u32,
+  code: Option<i32>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct State {
-  pub stable: Stable,
+/// Game processes started by `launch_game`, keyed by the executable path, so
+/// the same game can't be started twice while it is still running.
+#[derive(Default)]
+pub struct RunningGames {
+  children: Mutex<HashMap<String, u32>>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Stable {
-  pub version: String,
+impl RunningGames {
+  /// Whether a game started from inside `dir`, a canonical path, is still
+  /// running.
+  pub fn any_running_in(&self, dir: &Path) -> bool {
+    self
+      .children
+      .lock()
+      .map(|children| {
+        children.keys().any(|executable| {
+          Path::new(executable)
+            .canonicalize()
+            .is_ok_and(|executable| executable.starts_with(dir))
+        })
+      })
+      .unwrap_or(false)
+  }
 }
 
 #[command]
@@ -32,20 +110,312 @@ pub fn get_current_path() -> Result<String, SparusError> {
 }
 
 #[command]
-pub fn get_game_exe_name(path: String) -> Result<String, SparusError> {
-  let path = Path::new(&path);
+pub fn get_game_exe_name<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: Option<String>,
+  path: Option<String>,
+) -> Result<String, SparusError> {
+  let (path, game) = game_location(&app, game_id.as_deref(), path)?;
+  launch_config(&app, &path, game.as_ref())?
+    .executable
+    .ok_or(SparusError::Game("No Game installed".to_string()))
+}
+
+#[command]
+pub fn get_game_launch_config<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: Option<String>,
+  path: Option<String>,
+) -> Result<LaunchConfig, SparusError> {
+  let (path, game) = game_location(&app, game_id.as_deref(), path)?;
+  launch_config(&app, &path, game.as_ref())
+}
+
+/// The library game `game_id` and its workspace, or just `path` for callers
+/// that don't use the library.
+fn game_location<R: Runtime>(
+  app: &AppHandle<R>,
+  game_id: Option<&str>,
+  path: Option<String>,
+) -> Result<(PathBuf, Option<library::Game>), SparusError> {
+  match (game_id, path) {
+    (Some(game_id), _) => {
+      let game = library::game(app, game_id)?;
+      Ok((PathBuf::from(&game.workspace_path), Some(game)))
+    }
+    (None, Some(path)) => Ok((PathBuf::from(path), None)),
+    (None, None) => Err(SparusError::Game("No Game installed".to_string())),
+  }
+}
+
+/// Resolves the launch configuration of the game installed in `path` for the
+/// current OS.
+///
+/// A declared executable always wins. Auto-detection only fills in the
+/// executable when nothing was declared. The game installed without a library
+/// entry can be declared under `game_launch` in the bundled `Sparus.json`,
+/// and any game in the `launch.json` its repository ships. The store is left
+/// out: the webview can write it, and the launch configuration decides what
+/// runs.
+pub fn launch_config<R: Runtime>(
+  app: &AppHandle<R>,
+  path: &Path,
+  game: Option<&library::Game>,
+) -> Result<LaunchConfig, SparusError> {
+  if !path.is_dir() {
+    return Err(SparusError::Game("No Game installed".to_string()));
+  }
+
+  let store = app.store("Sparus.json")?;
+  let bundled = match game {
+    Some(_) => None,
+    None => settings::bundled_config(app)?.remove("game_launch"),
+  };
+  let declared = match bundled {
+    Some(config_json @ serde_json::Value::Object(_)) => {
+      Some(serde_json::from_value::<LaunchConfig>(config_json)?)
+    }
+    _ => {
+      let launch_file = path.join("launch.json");
+      if launch_file.is_file() {
+        Some(serde_json::from_reader::<_, LaunchConfig>(File::open(
+          &launch_file,
+        )?)?)
+      } else {
+        None
+      }
+    }
+  };
+
+  let mut config = declared.unwrap_or_default().for_current_os();
+  match config.executable {
+    Some(ref executable) => {
+      if !path.join(executable).is_file() {
+        return Err(SparusError::Game("No Game installed".to_string()));
+      }
+    }
+    None => {
+      let game_name = match (game, store.get("game_name")) {
+        (Some(game), _) => game.name.clone(),
+        (None, Some(tauri_plugin_store::JsonValue::String(game_name))) => game_name,
+        _ => String::new(),
+      };
+      config.executable = Some(detect_executable(path, &game_name)?);
+    }
+  }
+  Ok(config)
+}
+
+/// Name fragments of executables that ship next to a game without being the
+/// game: crash reporters, installers, redistributables...
+const HELPER_MARKERS: &[&str] = &[
+  "crash", "report", "unins", "setup", "install", "redist", "helper", "updater", "launcher",
+];
+const SCRIPT_EXTENSIONS: &[&str] = &["sh", "bat", "cmd", "command", "py"];
+
+struct Candidate {
+  name: String,
+  score: i32,
+  size: u64,
+}
+
+/// Picks the most likely game executable in `path`.
+///
+/// `read_dir` order is platform dependent, so every executable is scored
+/// instead of taking the first one: a name matching the game wins, helper
+/// binaries and script wrappers lose, and the biggest binary breaks ties.
+fn detect_executable(path: &Path, game_name: &str) -> Result<String, SparusError> {
   let folder =
     fs::read_dir(path).map_err(|_| SparusError::Game("No Game installed".to_string()))?;
+  let game_name = normalize_name(game_name);
+
+  let mut best: Option<Candidate> = None;
   for entry in folder {
     let entry = entry?;
     let meta = entry.metadata()?;
     let path = entry.path();
 
-    if meta.is_file() && is_executable(&path) {
-      return Ok(path.file_name().unwrap().to_string_lossy().to_string());
+    if !meta.is_file() || !is_executable(&path) {
+      continue;
+    }
+
+    let name = entry.file_name().to_string_lossy().to_string();
+    let stem = path
+      .file_stem()
+      .map(|stem| normalize_name(&stem.to_string_lossy()))
+      .unwrap_or_default();
+    let extension = path
+      .extension()
+      .map(|extension| extension.to_string_lossy().to_lowercase())
+      .unwrap_or_default();
+
+    let mut score = 0;
+    if !game_name.is_empty() {
+      if stem == game_name {
+        score += 200;
+      } else if stem.contains(&game_name) {
+        score += 100;
+      }
+    }
+    if HELPER_MARKERS.iter().any(|marker| stem.contains(marker)) {
+      score -= 100;
+    }
+    if SCRIPT_EXTENSIONS.contains(&extension.as_str()) {
+      score -= 20;
+    }
+
+    let candidate = Candidate {
+      name,
+      score,
+      size: meta.len(),
+    };
+    // Name order only keeps the pick stable across `read_dir` orders.
+    let is_better = match best {
+      Some(ref best) => (candidate.score, candidate.size)
+        .cmp(&(best.score, best.size))
+        .then_with(|| best.name.cmp(&candidate.name))
+        .is_gt(),
+      None => true,
+    };
+    if is_better {
+      best = Some(candidate);
+    }
+  }
+
+  best
+    .map(|candidate| candidate.name)
+    .ok_or(SparusError::Game("No Game installed".to_string()))
+}
+
+fn normalize_name(name: &str) -> String {
+  name
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .flat_map(char::to_lowercase)
+    .collect()
+}
+
+/// `relative`, a path from the launch configuration, resolved inside the game
+/// directory `dir`, a canonical path.
+fn inside_game_dir(dir: &Path, relative: &str) -> Result<PathBuf, SparusError> {
+  let outside = || SparusError::Game(format!("{relative} is not inside the game directory"));
+  if Path::new(relative)
+    .components()
+    .any(|component| !matches!(component, Component::Normal(_)))
+  {
+    return Err(outside());
+  }
+  let path = dir.join(relative);
+  // A symlink shipped with the game could still point outside of it.
+  match path.canonicalize() {
+    Ok(canonical) if !canonical.starts_with(dir) => Err(outside()),
+    _ => Ok(path),
+  }
+}
+
+/// Starts the library game `game_id`, or else the game installed in the
+/// default game directory of the workspace root, once the `on-before-launch`
+/// hooks of the plugins let it.
+///
+/// The directory, the executable, its environment and its working directory
+/// only come from the library and the launch configuration, never from the
+/// webview. The webview can only add the `args` the configuration lists in
+/// `allowed_args`. This command replaces a shell spawn the webview could point
+/// at anything, so it must not become a way to run arbitrary programs again.
+#[command]
+pub async fn launch_game<R: Runtime>(
+  app: AppHandle<R>,
+  running_games: tauri::State<'_, RunningGames>,
+  game_id: Option<String>,
+  args: Option<Vec<String>>,
+) -> Result<u32, SparusError> {
+  let (path, game) = match game_id.as_deref() {
+    Some(game_id) => {
+      let game = library::game(&app, game_id)?;
+      (PathBuf::from(&game.workspace_path), Some(game))
     }
+    None => (
+      library::workspace_root(&app)?.join(library::DEFAULT_GAME_DIR),
+      None,
+    ),
+  };
+  if !path.is_dir() {
+    return Err(SparusError::Game("No Game installed".to_string()));
+  }
+  let path = library::inside_workspace_root(&app, &path)?;
+
+  let config = launch_config(&app, &path, game.as_ref())?;
+  let executable = config
+    .executable
+    .ok_or(SparusError::Game("No Game installed".to_string()))?;
+  let extra_args = args.unwrap_or_default();
+  if let Some(arg) = extra_args
+    .iter()
+    .find(|arg| !config.allowed_args.contains(arg))
+  {
+    return Err(SparusError::Game(format!(
+      "{arg} is not an allowed argument of the game"
+    )));
+  }
+  let mut args = config.args;
+  args.extend(extra_args);
+
+  let executable_path = inside_game_dir(&path, &executable)?;
+  if !executable_path.is_file() {
+    return Err(SparusError::Game("No Game installed".to_string()));
+  }
+  let current_dir = match config.working_dir {
+    Some(working_dir) => inside_game_dir(&path, &working_dir)?,
+    None => path,
+  };
+  let executable_key = executable_path.to_string_lossy().to_string();
+
+  hooks::before_launch(&app, game_id.as_deref(), &executable).await?;
+
+  let mut children = running_games
+    .children
+    .lock()
+    .map_err(|_| SparusError::Game("Game process list is poisoned".to_string()))?;
+  if children.contains_key(&executable_key) {
+    return Err(SparusError::Game(format!(
+      "{executable} is already running"
+    )));
   }
-  Err(SparusError::Game("No Game installed".to_string()))
+
+  let mut command = Command::new(&executable_path);
+  command.args(args).envs(config.env).current_dir(current_dir);
+  let mut child = command.spawn()?;
+  let pid = child.id();
+  children.insert(executable_key.clone(), pid);
+  drop(children);
+
+  let _ = app.emit(
+    GAME_STARTED_EVENT,
+    GameStarted {
+      executable: executable.clone(),
+      pid,
+    },
+  );
+
+  tauri::async_runtime::spawn_blocking(move || {
+    let code = child.wait().ok().and_then(|status| status.code());
+    if let Ok(mut children) = app.state::<RunningGames>().children.lock() {
+      children.remove(&executable_key);
+    }
+    let _ = app.emit(
+      GAME_EXITED_EVENT,
+      GameExited {
+        executable,
+        pid,
+        code,
+      },
+    );
+    tauri::async_runtime::spawn(async move {
+      hooks::game_exit(&app, game_id.as_deref(), code).await;
+    });
+  });
+
+  Ok(pid)
 }
 
 fn is_executable(path: &Path) -> bool {
@@ -64,19 +434,46 @@ fn is_executable(path: &Path) -> bool {
   }
 }
 
-pub fn version<R: Runtime>(app: AppHandle<R>) -> Result<String, SparusError> {
-  let current_dir = get_current_path()?;
-  let current_path = Path::new(&current_dir);
-  let state_file = current_path.join(".update").join("state.json");
-  if state_file.is_file() {
-    let content = File::open(&state_file)?;
-    let root: Root = serde_json::from_reader(content)?;
-    Ok(root.state.stable.version)
+#[command]
+pub fn get_installed_version<R: Runtime>(
+  app: AppHandle<R>,
+  game_id: Option<String>,
+) -> Result<String, SparusError> {
+  let workspace_path = match game_id {
+    Some(game_id) => PathBuf::from(library::game(&app, &game_id)?.workspace_path),
+    None => env::current_dir()?,
+  };
+  version(app, &workspace_path)
+}
+
+/// Installed version of the workspace, from the state libspeedupdate keeps in
+/// `.update`. A workspace holds the files of a single channel, the one its
+/// game was last updated from, so the version needs no channel.
+pub fn version<R: Runtime>(
+  app: AppHandle<R>,
+  workspace_path: &Path,
+) -> Result<String, SparusError> {
+  if workspace_path.join(".update").join("state.json").is_file() {
+    state_version(Workspace::open(workspace_path)?.state()).ok_or(SparusError::NoVersion)
   } else {
     initial_version(app)
   }
 }
 
+/// Version a workspace in `state` is at. An update that is running, paused
+/// or failed leaves the workspace updating, still at the version it updates
+/// from, or at none yet when it is the first install, in which case the
+/// version it installs is the one to report.
+fn state_version(state: &WorkspaceState) -> Option<String> {
+  match state {
+    WorkspaceState::Stable { version } | WorkspaceState::Corrupted { version, .. } => {
+      Some(version.to_string())
+    }
+    WorkspaceState::Updating { from, to } => Some(from.as_ref().unwrap_or(to).to_string()),
+    WorkspaceState::New => None,
+  }
+}
+
 fn initial_version<R: Runtime>(app: AppHandle<R>) -> Result<String, SparusError> {
   let store = app.store("Sparus.json")?;
   match store.get("initial_version") {
@@ -90,3 +487,53 @@ fn initial_version<R: Runtime>(app: AppHandle<R>) -> Result<String, SparusError>
     None => Err(SparusError::NoVersion),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use libspeedupdate::metadata::CleanName;
+
+  fn name(version: &str) -> CleanName {
+    CleanName::new(version.to_string()).unwrap()
+  }
+
+  #[test]
+  fn new_workspace_has_no_version() {
+    assert_eq!(state_version(&WorkspaceState::New), None);
+  }
+
+  #[test]
+  fn stable_workspace_is_at_its_version() {
+    let state = WorkspaceState::Stable {
+      version: name("1.2.0"),
+    };
+    assert_eq!(state_version(&state).as_deref(), Some("1.2.0"));
+  }
+
+  #[test]
+  fn corrupted_workspace_is_at_its_version() {
+    let state = WorkspaceState::Corrupted {
+      version: name("1.2.0"),
+      failures: Vec::new(),
+    };
+    assert_eq!(state_version(&state).as_deref(), Some("1.2.0"));
+  }
+
+  #[test]
+  fn updating_workspace_is_at_the_version_it_updates_from() {
+    let state = WorkspaceState::Updating {
+      from: Some(name("1.2.0")),
+      to: name("1.3.0"),
+    };
+    assert_eq!(state_version(&state).as_deref(), Some("1.2.0"));
+  }
+
+  #[test]
+  fn first_install_is_at_the_version_it_installs() {
+    let state = WorkspaceState::Updating {
+      from: None,
+      to: name("1.3.0"),
+    };
+    assert_eq!(state_version(&state).as_deref(), Some("1.3.0"));
+  }
+}
@@ -24,7 +24,6 @@ import { SparusErrorContext, SparusStoreContext } from "utils/Context";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { relaunch } from "@tauri-apps/plugin-process";
-import { Command, SpawnOptions } from "@tauri-apps/plugin-shell";
 import { arch, platform } from "@tauri-apps/plugin-os";
 import {
   isPermissionGranted,
@@ -97,7 +96,9 @@ function Footer() {
   const [loading, setLoading] = useState<boolean>(false);
   const [open, setOpen] = useState(false);
   const [workspacePath, setWorkspacePath] = useState<string>("");
-  const [gameName, setGameName] = useState<string>("");
+  // The library game to launch. Without one, the launcher starts the game of
+  // the workspace's `game` directory.
+  const [gameId, setGameId] = useState<string>();
   const [repositoryName, setRepositoryName] = useState<string>("");
   const [repositoryUrl, setRepositoryUrl] = useState<string>("");
   const [downloadedBytesStart, setDownloadedBytesStart] = useState("");
@@ -116,20 +117,12 @@ function Footer() {
   const alternativeLabel = activeSource === "game" ? launcherLabel : gameLabel;
 
   let platform = "";
-  let shell = "";
-  let arg: string[] = [""];
 
   if (host === "windows") {
-    shell = "cmd";
-    arg = ["/C"];
     platform = "win64";
   } else if (host === "linux") {
     platform = "linux";
-    shell = "sh";
-    arg = ["-c"];
   } else if (host === "macos") {
-    shell = "sh";
-    arg = ["-c"];
     if (architecture === "x86_64") {
       platform = "macos_x86_64";
     }
@@ -176,43 +169,31 @@ function Footer() {
 
   useEffect(() => {
     Promise.all([
-      store.get<string>("games"),
       store.get<string>("repository_name"),
       store.get<string>("repository_url"),
-      store.get<string>("workspace_path"),
+      invoke<string | null>("get_workspace_root"),
     ])
-      .then(([games, repository_name, repository_url, workspace_path]) => {
-        if (games) setGameName(games[0]);
+      .then(([repository_name, repository_url, workspace_path]) => {
         if (repository_name) setRepositoryName(repository_name);
         if (repository_url) setRepositoryUrl(repository_url);
-        if (workspace_path !== undefined) setWorkspacePath(workspace_path);
+        // Until an installation folder is chosen in the options, there is no
+        // game to look for.
+        if (!workspace_path) {
+          setGameState("not_installed");
+          return;
+        }
+        setWorkspacePath(workspace_path);
 
-        invoke<string>("get_current_path")
-          .then((path) => {
-            store.set("workspace_path", path).catch((err: unknown) => {
-              let error: SparusError = {
-                kind: "path",
-                message: "Failed to set workspace path: ".concat(err as string),
-              };
-              setGlobalError(error);
-            });
-            setWorkspacePath(path);
-          })
-          .catch((err: unknown) => {
-            let error: SparusError = {
-              kind: "path",
-              message: "Failed to get current path: ".concat(err as string),
-            };
-            setGlobalError(error);
-          });
+        invoke<{ id: string }[]>("list_games")
+          .then((libraryGames) => setGameId(libraryGames[0]?.id))
+          .catch((err: unknown) => setGlobalError(err as SparusError));
 
         const workdirSubPath = host === "windows" ? "\\game" : "/game";
-        const gameWorkspacePath = (workspace_path ?? workspacePath).concat(workdirSubPath);
+        const gameWorkspacePath = workspace_path.concat(workdirSubPath);
         invoke<string>("get_game_exe_name", {
           path: gameWorkspacePath,
         })
-          .then((name) => {
-            setGameName(name);
+          .then(() => {
             setGameState("play");
           })
           .catch((err: unknown) => {
@@ -335,12 +316,12 @@ function Footer() {
   }, [gameState, launcherState, globalError]);
 
   const spawn = () => {
-    const opts: SpawnOptions = {
-      env: { CARGO_MANIFEST_DIR: workspacePath.concat("/game/") },
-    };
-    const command = Command.create(shell, [...arg, workspacePath.concat("/game/", gameName)], opts);
-
-    command.spawn().catch((err: unknown) => {
+    invoke("launch_game", { gameId }).catch((err: unknown) => {
+      // A plugin refused the launch, its reason is meant for the player.
+      if ((err as SparusError).kind === "launch_vetoed") {
+        setGlobalError(err as SparusError);
+        return;
+      }
       let error: SparusError = {
         kind: "update",
         message: "Failed to spawn command: ".concat(err as string),
@@ -14,8 +14,7 @@ import { PluginSlot } from "utils/usePlugins";
 // Tauri api
 import { remove } from "@tauri-apps/plugin-fs";
 import { enable, disable } from "@tauri-apps/plugin-autostart";
-import { open } from "@tauri-apps/plugin-dialog";
-import { platform } from "@tauri-apps/plugin-os";
+import { invoke } from "@tauri-apps/api/core";
 
 // Icons
 import DeleteIcon from "@mui/icons-material/Delete";
@@ -26,8 +25,6 @@ interface SparusError {
   message: string;
 }
 
-const host = platform();
-
 function Options() {
   const [autostart, setAutostart] = useState<boolean>();
   const [repositoryUrl, setRepositoryUrl] = useState<string>("");
@@ -39,7 +36,7 @@ function Options() {
   useEffect(() => {
     Promise.all([
       store.get<string>("repository_url"),
-      store.get<string>("workspace_path"),
+      invoke<string | null>("get_workspace_root"),
       store.get<boolean>("autostart"),
     ])
       .then(([repository_url, workspace_path, autostart]) => {
@@ -100,36 +97,18 @@ function Options() {
                 type="text"
                 variant="standard"
                 value={workspacePath}
-                onChange={(event) => {
-                  setWorkspacePath(event.target.value);
-                  store.set("workspace_path", event.target.value).catch((err: unknown) => {
-                    setGlobalError(
-                      (err as SparusError).kind.concat(": ", (err as SparusError).message),
-                    );
-                  });
-                }}
+                // Only the native folder picker can change the installation
+                // folder.
+                slotProps={{ input: { readOnly: true } }}
               />
             </Grid>
             <Grid size={1}>
               <IconButton
                 aria-label="folder"
                 onClick={() => {
-                  open({
-                    multiple: false,
-                    directory: true,
-                  })
+                  invoke<string | null>("choose_workspace_root")
                     .then((dir) => {
-                      if (dir) {
-                        const gameSubPath = host === "windows" ? "\\game" : "/game";
-                        setWorkspacePath(dir.concat(gameSubPath));
-                        store
-                          .set("workspace_path", dir.concat(gameSubPath))
-                          .catch((err: unknown) => {
-                            setGlobalError(
-                              (err as SparusError).kind.concat(": ", (err as SparusError).message),
-                            );
-                          });
-                      }
+                      if (dir) setWorkspacePath(dir);
                     })
                     .catch((err: unknown) => {
                       setGlobalError(
@@ -1,6 +1,6 @@
 import { ModuleFederation } from "@module-federation/runtime";
 import { useState, useEffect, useContext, ReactElement, ComponentType } from "react";
-import { convertFileSrc } from "@tauri-apps/api/core";
+import { convertFileSrc, invoke } from "@tauri-apps/api/core";
 import { appDataDir, join } from "@tauri-apps/api/path";
 import { SparusErrorContext } from "utils/Context";
 
@@ -31,6 +31,15 @@ export const Plugins = ({ path, register, mf }: PluginsManagerProps & { mf: Modu
   useEffect(() => {
     let cancelled = false;
 
+    // The launcher counts the failed loads of installed plugins, and
+    // quarantines the ones that keep failing.
+    const reportLoad = (error: string | null) => {
+      if (import.meta.env.DEV) return;
+      invoke("report_plugin_load", { plugin: path, error }).catch((err: unknown) =>
+        setGlobalError(err),
+      );
+    };
+
     const loadPlugin = (entry: string) => {
       // Cache-bust the bundle URL so an updated frontend.js is fetched fresh
       // instead of served stale from the webview cache (#1037). `force` lets the
@@ -44,12 +53,15 @@ export const Plugins = ({ path, register, mf }: PluginsManagerProps & { mf: Modu
           if (cancelled) return;
           if (!isRemoteModule(mod)) {
             setGlobalError(`plugin "${path}" is invalid (no default export)`);
+            reportLoad("no default export");
             return;
           }
+          reportLoad(null);
           setComponent(() => mod.default);
         })
         .catch((err: Error) => {
           if (cancelled) return;
+          reportLoad(err.message);
           if (!(err instanceof TypeError) && !err.message.includes("Failed to fetch")) {
             setGlobalError(err);
           }